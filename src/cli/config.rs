@@ -0,0 +1,125 @@
+use super::editorconfig::find_editorconfig;
+use satysfi_formatter::{EndOfLine, IndentStyle, OptionData, SourceKind};
+use serde::{de, Deserialize, Deserializer};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// プロジェクトの設定ファイル名
+pub const CONFIG_FILE_NAME: &str = "satysfi-fmt.toml";
+
+/// `satysfi-fmt.toml` の内容
+/// 指定されなかった項目は `OptionData` の値をそのまま使う
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub row_length: Option<usize>,
+    pub indent_space: Option<usize>,
+    pub command_args_space: Option<bool>,
+    pub align_trailing_comments: Option<bool>,
+    pub wrap_comments: Option<bool>,
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub indent_style: Option<IndentStyle>,
+    pub tab_width: Option<usize>,
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub end_of_line: Option<EndOfLine>,
+    pub insert_final_newline: Option<bool>,
+    pub sort_headers: Option<bool>,
+    pub fill_text: Option<bool>,
+}
+
+/// `indent_style = "tab"` のような文字列で指定する項目を読み込む
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    value
+        .map(|value| value.parse().map_err(de::Error::custom))
+        .transpose()
+}
+
+impl Config {
+    /// 設定を `option` に上書きする
+    pub fn apply(&self, option: &mut OptionData) {
+        if let Some(row_length) = self.row_length {
+            option.row_length = row_length;
+        }
+        if let Some(indent_space) = self.indent_space {
+            option.indent_space = indent_space;
+        }
+        if let Some(command_args_space) = self.command_args_space {
+            option.command_args_space = command_args_space;
+        }
+        if let Some(align_trailing_comments) = self.align_trailing_comments {
+            option.align_trailing_comments = align_trailing_comments;
+        }
+        if let Some(wrap_comments) = self.wrap_comments {
+            option.wrap_comments = wrap_comments;
+        }
+        if let Some(indent_style) = self.indent_style {
+            option.indent_style = indent_style;
+        }
+        if let Some(tab_width) = self.tab_width {
+            option.tab_width = tab_width;
+        }
+        if let Some(end_of_line) = self.end_of_line {
+            option.end_of_line = end_of_line;
+        }
+        if let Some(insert_final_newline) = self.insert_final_newline {
+            option.insert_final_newline = insert_final_newline;
+        }
+        if let Some(sort_headers) = self.sort_headers {
+            option.sort_headers = sort_headers;
+        }
+        if let Some(fill_text) = self.fill_text {
+            option.fill_text = fill_text;
+        }
+    }
+}
+
+/// `path` のあるディレクトリから親を辿って設定ファイルを探す
+/// `path` が `None` のときはカレントディレクトリから探す
+pub fn find_config(path: Option<&Path>) -> Option<PathBuf> {
+    let dir = path
+        .and_then(|path| path.parent())
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let dir = env::current_dir().ok()?.join(dir);
+    dir.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|config| config.is_file())
+}
+
+/// 設定ファイルを読み込む
+pub fn load_config(path: &Path) -> io::Result<Config> {
+    let text = fs::read_to_string(path)?;
+    toml::from_str(&text).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {err}", path.display()),
+        )
+    })
+}
+
+/// `path` の format の設定を決める
+/// デフォルト値, `.editorconfig`, 設定ファイル, `overrides` (CLI の引数やエディタの設定) の順に上書きする
+/// `path` が `None` (標準入力) のときは `.editorconfig` を使わず、カレントディレクトリから設定ファイルを探す
+pub fn resolve_option(path: Option<&Path>, overrides: &Config) -> io::Result<OptionData> {
+    let mut option = OptionData {
+        command_args_space: false,
+        source_kind: path.map_or(SourceKind::Auto, SourceKind::from_path),
+        ..Default::default()
+    };
+    if let Some(path) = path {
+        find_editorconfig(path)?.apply(&mut option);
+    }
+    if let Some(config) = find_config(path) {
+        load_config(&config)?.apply(&mut option);
+    }
+    overrides.apply(&mut option);
+    Ok(option)
+}
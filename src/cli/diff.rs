@@ -0,0 +1,48 @@
+use similar::TextDiff;
+
+/// 差分表示の前後に表示する行数
+const CONTEXT_RADIUS: usize = 3;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// `original` と `formatted` の unified diff を返す
+/// 差分がない場合は空文字列を返す
+/// * `name` - ヘッダに表示するファイル名
+/// * `color` - ANSI escape で色を付けるかどうか
+pub fn unified_diff(name: &str, original: &str, formatted: &str, color: bool) -> String {
+    if original == formatted {
+        return String::new();
+    }
+    let diff = TextDiff::from_lines(original, formatted);
+    let output = diff
+        .unified_diff()
+        .context_radius(CONTEXT_RADIUS)
+        .header(&format!("a/{name}"), &format!("b/{name}"))
+        .to_string();
+
+    if !color {
+        return output;
+    }
+    output
+        .lines()
+        .map(|line| {
+            let color = if line.starts_with("---") || line.starts_with("+++") {
+                BOLD
+            } else if line.starts_with("@@") {
+                CYAN
+            } else if line.starts_with('+') {
+                GREEN
+            } else if line.starts_with('-') {
+                RED
+            } else {
+                return line.to_string();
+            };
+            format!("{color}{line}{RESET}")
+        })
+        .map(|line| line + "\n")
+        .collect()
+}
@@ -0,0 +1,315 @@
+use satysfi_formatter::OptionData;
+use std::{collections::HashMap, env, fs, io, path::Path};
+
+/// EditorConfig のファイル名
+pub const EDITORCONFIG_FILE_NAME: &str = ".editorconfig";
+
+/// あるファイルに当てはまる `.editorconfig` の設定
+#[derive(Debug, Default)]
+pub struct EditorConfig {
+    properties: HashMap<String, String>,
+}
+
+impl EditorConfig {
+    /// 設定を `option` に上書きする
+    /// 解釈できない値は無視する
+    pub fn apply(&self, option: &mut OptionData) {
+        if let Some(style) = self
+            .get("indent_style")
+            .and_then(|style| style.parse().ok())
+        {
+            option.indent_style = style;
+        }
+        if let Some(tab_width) = self.get("tab_width").and_then(|width| width.parse().ok()) {
+            option.tab_width = tab_width;
+        }
+        match self.get("indent_size") {
+            // タブの幅と同じにする
+            Some("tab") => option.indent_space = option.tab_width,
+            Some(size) => {
+                if let Ok(size) = size.parse() {
+                    option.indent_space = size;
+                }
+            }
+            None => {}
+        }
+        if let Some(length) = self
+            .get("max_line_length")
+            .and_then(|length| length.parse().ok())
+        {
+            option.row_length = length;
+        }
+        if let Some(end_of_line) = self.get("end_of_line").and_then(|eol| eol.parse().ok()) {
+            option.end_of_line = end_of_line;
+        }
+        match self.get("insert_final_newline") {
+            Some("true") => option.insert_final_newline = true,
+            Some("false") => option.insert_final_newline = false,
+            _ => {}
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+}
+
+/// `path` のあるディレクトリから親を辿って `.editorconfig` を探し、`path` に当てはまる設定を集める
+/// `root = true` の `.editorconfig` より上は探さない
+pub fn find_editorconfig(path: &Path) -> io::Result<EditorConfig> {
+    let path = env::current_dir()?.join(path);
+    let mut files = vec![];
+    for dir in path.ancestors().skip(1) {
+        let file = dir.join(EDITORCONFIG_FILE_NAME);
+        if !file.is_file() {
+            continue;
+        }
+        let (root, sections) = parse_editorconfig(&fs::read_to_string(&file)?);
+        files.push((dir.to_path_buf(), sections));
+        if root {
+            break;
+        }
+    }
+
+    // 遠いファイルから順に、近いファイルの設定で上書きする
+    let mut config = EditorConfig::default();
+    for (dir, sections) in files.iter().rev() {
+        let relative = match path.strip_prefix(dir) {
+            Ok(relative) => relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            Err(_) => continue,
+        };
+        for section in sections.iter() {
+            if section_matches(&section.pattern, &relative) {
+                for (key, value) in section.properties.iter() {
+                    config.properties.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// `[pattern]` から始まる設定のまとまり
+#[derive(Debug)]
+struct Section {
+    pattern: String,
+    properties: Vec<(String, String)>,
+}
+
+/// `.editorconfig` を読み、`root = true` かどうかと各 section を返す
+fn parse_editorconfig(text: &str) -> (bool, Vec<Section>) {
+    let mut root = false;
+    let mut sections: Vec<Section> = vec![];
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(pattern) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            sections.push(Section {
+                pattern: pattern.to_string(),
+                properties: vec![],
+            });
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim().to_lowercase()),
+            None => continue,
+        };
+        match sections.last_mut() {
+            Some(section) => section.properties.push((key, value)),
+            // 最初の section より前には root だけを書ける
+            None => root = key == "root" && value == "true",
+        }
+    }
+    (root, sections)
+}
+
+/// section の pattern が `.editorconfig` のあるディレクトリからの相対パスに当てはまるか
+/// `/` を含まない pattern はどの階層のファイル名にも当てはまる
+fn section_matches(pattern: &str, relative: &str) -> bool {
+    let (pattern, text) = if pattern.contains('/') {
+        (pattern.strip_prefix('/').unwrap_or(pattern), relative)
+    } else {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        (pattern, name)
+    };
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    glob_match(&pattern, &text)
+}
+
+/// EditorConfig の glob で照合する
+/// `*`, `**`, `?`, `[...]`, `[!...]`, `{a,b}` に対応する
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            // `**` は `/` を含む任意の文字列
+            (0..=text.len()).any(|len| glob_match(&pattern[2..], &text[len..]))
+        }
+        Some('*') => (0..=text.len())
+            .take_while(|&len| len == 0 || text[len - 1] != '/')
+            .any(|len| glob_match(&pattern[1..], &text[len..])),
+        Some('?') => match text.first() {
+            Some(&c) if c != '/' => glob_match(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some('[') => match pattern.iter().position(|&c| c == ']') {
+            Some(close) if close > 1 => {
+                let (negate, class) = match pattern[1] {
+                    '!' => (true, &pattern[2..close]),
+                    _ => (false, &pattern[1..close]),
+                };
+                match text.first() {
+                    Some(&c) if c != '/' && class_contains(class, c) != negate => {
+                        glob_match(&pattern[close + 1..], &text[1..])
+                    }
+                    _ => false,
+                }
+            }
+            _ => literal_match(pattern, text),
+        },
+        Some('{') => match split_braces(pattern) {
+            Some((alternatives, rest)) => alternatives.iter().any(|alternative| {
+                let pattern = alternative.iter().chain(rest).copied().collect::<Vec<_>>();
+                glob_match(&pattern, text)
+            }),
+            None => literal_match(pattern, text),
+        },
+        Some('\\') if pattern.len() > 1 => literal_match(&pattern[1..], text),
+        Some(_) => literal_match(pattern, text),
+    }
+}
+
+/// pattern の先頭の 1 文字をそのまま照合する
+fn literal_match(pattern: &[char], text: &[char]) -> bool {
+    text.first() == pattern.first() && glob_match(&pattern[1..], &text[1..])
+}
+
+/// `[a-z]` のような文字の集合に `c` が含まれるか
+fn class_contains(class: &[char], c: char) -> bool {
+    let mut index = 0;
+    while index < class.len() {
+        if index + 2 < class.len() && class[index + 1] == '-' {
+            if class[index] <= c && c <= class[index + 2] {
+                return true;
+            }
+            index += 3;
+        } else {
+            if class[index] == c {
+                return true;
+            }
+            index += 1;
+        }
+    }
+    false
+}
+
+/// `{a,b}rest` を選択肢 `[a, b]` と残り `rest` に分ける
+/// 対応する `}` が無いか、`,` を含まない場合は `None`
+fn split_braces(pattern: &[char]) -> Option<(Vec<Vec<char>>, &[char])> {
+    let mut depth = 0;
+    let mut alternatives = vec![];
+    let mut current = vec![];
+    for (index, &c) in pattern.iter().enumerate().skip(1) {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => {
+                alternatives.push(current);
+                return if alternatives.len() > 1 {
+                    Some((alternatives, &pattern[index + 1..]))
+                } else {
+                    None
+                };
+            }
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use satysfi_formatter::{EndOfLine, IndentStyle};
+
+    #[test]
+    fn test_section_matches() {
+        assert!(section_matches("*", "src/main.saty"));
+        assert!(section_matches("*.saty", "doc/main.saty"));
+        assert!(!section_matches("*.saty", "doc/main.satyh"));
+        assert!(section_matches("*.{saty,satyh}", "lib/pkg.satyh"));
+        assert!(section_matches("doc/*.saty", "doc/main.saty"));
+        assert!(!section_matches("doc/*.saty", "doc/sub/main.saty"));
+        assert!(section_matches("/doc/**.saty", "doc/sub/main.saty"));
+        assert!(section_matches("ma?n.saty[!h]", "main.satyg"));
+        assert!(!section_matches("ma?n.saty[!h]", "main.satyh"));
+    }
+
+    #[test]
+    fn test_find_editorconfig() {
+        let base = env::temp_dir().join(format!("satysfi-fmt-editorconfig-{}", std::process::id()));
+        let root = base.join("root");
+        let doc = root.join("doc");
+        fs::create_dir_all(&doc).unwrap();
+        // root = true より上のファイルは読まない
+        fs::write(
+            base.join(EDITORCONFIG_FILE_NAME),
+            "[*]\nmax_line_length = 40\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join(EDITORCONFIG_FILE_NAME),
+            r#"root = true
+
+[*]
+indent_style = space
+indent_size = 2
+end_of_line = CRLF
+
+[*.saty]
+indent_style = tab
+tab_width = 8
+insert_final_newline = false
+"#,
+        )
+        .unwrap();
+        // 近いファイルの設定が優先され、パターンはそのファイルからの相対パスで比べる
+        fs::write(
+            doc.join(EDITORCONFIG_FILE_NAME),
+            r#"[sub/*.saty]
+tab_width = 2
+max_line_length = 100
+
+[*.satyh]
+indent_size = 3
+"#,
+        )
+        .unwrap();
+        let config = find_editorconfig(&doc.join("sub").join("main.saty"));
+        fs::remove_dir_all(&base).unwrap();
+
+        let mut option = OptionData::default();
+        config.unwrap().apply(&mut option);
+        assert_eq!(option.indent_style, IndentStyle::Tab);
+        assert_eq!(option.indent_space, 2);
+        assert_eq!(option.tab_width, 2);
+        assert_eq!(option.row_length, 100);
+        assert_eq!(option.end_of_line, EndOfLine::Crlf);
+        assert!(!option.insert_final_newline);
+    }
+}
@@ -0,0 +1,53 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// format 対象とする SATySFi の拡張子
+const SATYSFI_EXTENSIONS: [&str; 3] = ["saty", "satyh", "satyg"];
+
+/// SATySFi のソースファイルかどうかを拡張子で判定する
+pub fn is_satysfi_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SATYSFI_EXTENSIONS.contains(&ext))
+}
+
+/// 引数で与えられたパスを format 対象のファイルの一覧に展開する
+/// ディレクトリは再帰的に探索し、SATySFi の拡張子を持つファイルのみを拾う
+/// 明示的に指定されたファイルは拡張子に関わらず対象にする
+pub fn collect_files(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            walk_dir(path, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn walk_dir(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    // 出力順を安定させる
+    entries.sort();
+    for path in entries {
+        let hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'));
+        if hidden {
+            // .git などは探索しない
+            continue;
+        }
+        if path.is_dir() {
+            walk_dir(&path, files)?;
+        } else if is_satysfi_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
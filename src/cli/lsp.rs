@@ -0,0 +1,489 @@
+use super::config::{resolve_option, Config};
+use lsp_server::{
+    Connection, ErrorCode, ExtractError, Message, Notification, Request, RequestId, Response,
+};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{Formatting, OnTypeFormatting, RangeFormatting, Request as _},
+    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DocumentFormattingParams, DocumentOnTypeFormattingOptions,
+    DocumentOnTypeFormattingParams, DocumentRangeFormattingParams, FormattingOptions, OneOf,
+    Position, PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Url,
+};
+use satysfi_formatter::{
+    check_syntax, format_range, try_format, FormatError, IndentStyle, OptionData, SourceKind,
+};
+use serde::de::DeserializeOwned;
+use std::{collections::HashMap, error::Error};
+
+type LspResult<T> = Result<T, Box<dyn Error + Sync + Send>>;
+
+/// 入力中に format する契機となる文字
+const TRIGGER_CHARACTERS: [&str; 4] = ["}", ">", "]", ";"];
+
+/// 標準入出力で Language Server Protocol を話すサーバを起動する
+pub fn run() -> LspResult<()> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+/// `connection` で初期化を行い、shutdown を受け取るまで応答する
+fn serve(connection: &Connection) -> LspResult<()> {
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+            first_trigger_character: TRIGGER_CHARACTERS[0].to_string(),
+            more_trigger_character: Some(
+                TRIGGER_CHARACTERS[1..]
+                    .iter()
+                    .map(|ch| ch.to_string())
+                    .collect(),
+            ),
+        }),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut server = Server {
+        connection,
+        documents: HashMap::new(),
+    };
+    server.main_loop()
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    /// 開かれている文書の内容
+    documents: HashMap<Url, String>,
+}
+
+impl Server<'_> {
+    fn main_loop(&mut self) -> LspResult<()> {
+        for message in &self.connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    self.handle_request(request)?;
+                }
+                Message::Notification(notification) => self.handle_notification(notification)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, request: Request) -> LspResult<()> {
+        let response = match request.method.as_str() {
+            Formatting::METHOD => {
+                extract::<DocumentFormattingParams>(request).map(|(id, params)| {
+                    let edits = self.formatting(&params.text_document.uri, &params.options, None);
+                    Response::new_ok(id, edits)
+                })
+            }
+            RangeFormatting::METHOD => {
+                extract::<DocumentRangeFormattingParams>(request).map(|(id, params)| {
+                    let edits = self.formatting(
+                        &params.text_document.uri,
+                        &params.options,
+                        Some(params.range),
+                    );
+                    Response::new_ok(id, edits)
+                })
+            }
+            OnTypeFormatting::METHOD => {
+                extract::<DocumentOnTypeFormattingParams>(request).map(|(id, params)| {
+                    let position = params.text_document_position;
+                    // 入力した行だけを format する
+                    let line = Range::new(
+                        Position::new(position.position.line, 0),
+                        Position::new(position.position.line + 1, 0),
+                    );
+                    let edits =
+                        self.formatting(&position.text_document.uri, &params.options, Some(line));
+                    Response::new_ok(id, edits)
+                })
+            }
+            _ => Err(Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unknown method: {}", request.method),
+            )),
+        };
+        // 引数を読めなかった場合はエラーを応答する
+        let response = response.unwrap_or_else(|response| response);
+        self.connection.sender.send(Message::Response(response))?;
+        Ok(())
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> LspResult<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = match notification_params(notification) {
+                    Some(params) => params,
+                    None => return Ok(()),
+                };
+                let uri = params.text_document.uri;
+                self.documents
+                    .insert(uri.clone(), params.text_document.text);
+                self.publish_diagnostics(&uri)?;
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams = match notification_params(notification) {
+                    Some(params) => params,
+                    None => return Ok(()),
+                };
+                let uri = params.text_document.uri;
+                // 全文を同期しているので、最後の変更が文書全体になる
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(uri.clone(), change.text);
+                }
+                self.publish_diagnostics(&uri)?;
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = match notification_params(notification) {
+                    Some(params) => params,
+                    None => return Ok(()),
+                };
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                // 閉じた文書の診断は消す
+                self.publish_diagnostics(&uri)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// 文書を format する編集を返す
+    /// `range` が指定された場合はその範囲だけを format する
+    /// parse に失敗した場合は診断で報告しているので `None` を返す
+    fn formatting(
+        &self,
+        uri: &Url,
+        options: &FormattingOptions,
+        range: Option<Range>,
+    ) -> Option<Vec<TextEdit>> {
+        let text = self.documents.get(uri)?;
+        let option = option(uri, options)?;
+        match range {
+            None => {
+                let output = try_format(text, option).ok()?;
+                let range = Range::new(Position::new(0, 0), position_at(text, text.len()));
+                Some(vec![TextEdit::new(range, output)])
+            }
+            Some(range) => {
+                let range = offset_at(text, range.start)..offset_at(text, range.end);
+                let edits = format_range(text, range, option).ok()?;
+                let edits = edits
+                    .into_iter()
+                    .map(|edit| {
+                        let range = Range::new(
+                            position_at(text, edit.range.start),
+                            position_at(text, edit.range.end),
+                        );
+                        TextEdit::new(range, edit.new_text)
+                    })
+                    .collect();
+                Some(edits)
+            }
+        }
+    }
+
+    /// parse に失敗した箇所を診断として送る
+    /// 開かれていない文書には空の診断を送る
+    fn publish_diagnostics(&self, uri: &Url) -> LspResult<()> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(text) => diagnostics(uri, text),
+            None => vec![],
+        };
+        let params = PublishDiagnosticsParams::new(uri.clone(), diagnostics, None);
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        self.connection
+            .sender
+            .send(Message::Notification(notification))?;
+        Ok(())
+    }
+}
+
+/// 文書の parse に失敗した箇所の診断
+fn diagnostics(uri: &Url, text: &str) -> Vec<Diagnostic> {
+    let source_kind = uri
+        .to_file_path()
+        .map_or(SourceKind::Auto, |path| SourceKind::from_path(&path));
+    // 入力のたびに呼ばれるので、format はせずに parse だけを行う
+    match check_syntax(text, source_kind) {
+        Ok(_) => vec![],
+        Err(err) => {
+            let (line, column) = match &err {
+                FormatError::Parse { line, column, .. }
+                | FormatError::Incomplete { line, column, .. }
+                | FormatError::NotEquivalent { line, column, .. }
+                | FormatError::InvalidOutput { line, column } => (*line, *column),
+                FormatError::NotIdempotent { line, .. } => (*line, 1),
+            };
+            let position = position_at_line_col(text, line, column);
+            vec![Diagnostic {
+                range: Range::new(position, position),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("satysfi-fmt".to_string()),
+                message: err.to_string(),
+                ..Default::default()
+            }]
+        }
+    }
+}
+
+/// request の引数を取り出す
+/// 読めなかった場合は InvalidParams のエラーの応答を返す
+fn extract<P: DeserializeOwned>(request: Request) -> Result<(RequestId, P), Response> {
+    let id = request.id.clone();
+    let method = request.method.clone();
+    request.extract(&method).map_err(|err| {
+        let message = match err {
+            ExtractError::JsonError { method, error } => format!("{method}: {error}"),
+            ExtractError::MethodMismatch(request) => format!("unexpected {}", request.method),
+        };
+        Response::new_err(id, ErrorCode::InvalidParams as i32, message)
+    })
+}
+
+/// 通知の引数を取り出す
+/// 通知には応答できないので、読めなかった場合は報告して `None` を返す
+fn notification_params<P: DeserializeOwned>(notification: Notification) -> Option<P> {
+    let method = notification.method.clone();
+    serde_json::from_value(notification.params)
+        .map_err(|err| eprintln!("satysfi-fmt: {method}: {err}"))
+        .ok()
+}
+
+/// CLI と同じく `.editorconfig` と設定ファイルから format の設定を決め、エディタの設定で上書きする
+/// 設定ファイルを読めなかった場合は報告して `None` を返す
+fn option(uri: &Url, options: &FormattingOptions) -> Option<OptionData> {
+    let path = uri.to_file_path().ok();
+    let mut overrides = Config::default();
+    if options.tab_size > 0 {
+        overrides.indent_space = Some(options.tab_size as usize);
+        overrides.tab_width = Some(options.tab_size as usize);
+    }
+    if !options.insert_spaces {
+        overrides.indent_style = Some(IndentStyle::Tab);
+    }
+    resolve_option(path.as_deref(), &overrides)
+        .map_err(|err| eprintln!("satysfi-fmt: {err}"))
+        .ok()
+}
+
+/// LSP の位置 (行と UTF-16 での列) を byte 位置に変換する
+fn offset_at(text: &str, position: Position) -> usize {
+    let start = line_starts(text)
+        .get(position.line as usize)
+        .copied()
+        .unwrap_or(text.len());
+    let mut column = 0;
+    for (index, ch) in text[start..].char_indices() {
+        let line_end = ch == '\n' || text[start + index..].starts_with("\r\n");
+        if line_end || column >= position.character as usize {
+            return start + index;
+        }
+        column += ch.len_utf16();
+    }
+    text.len()
+}
+
+/// byte 位置を LSP の位置に変換する
+fn position_at(text: &str, offset: usize) -> Position {
+    let offset = offset.min(text.len());
+    let starts = line_starts(text);
+    let line = starts.iter().filter(|&&start| start <= offset).count() - 1;
+    let character = text[starts[line]..offset]
+        .chars()
+        .map(char::len_utf16)
+        .sum::<usize>();
+    Position::new(line as u32, character as u32)
+}
+
+/// 1 から数えた行と文字数での列を LSP の位置に変換する
+fn position_at_line_col(text: &str, line: usize, column: usize) -> Position {
+    let start = line_starts(text)
+        .get(line.saturating_sub(1))
+        .copied()
+        .unwrap_or(text.len());
+    let offset = text[start..]
+        .char_indices()
+        .nth(column.saturating_sub(1))
+        .map_or(text.len(), |(index, _)| start + index);
+    position_at(text, offset)
+}
+
+fn line_starts(text: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::{
+        notification::{Exit, Initialized},
+        request::{Initialize, Shutdown},
+        InitializeParams, InitializedParams, TextDocumentIdentifier, TextDocumentItem,
+    };
+    use std::{env, fs, process, thread};
+
+    #[test]
+    fn test_position() {
+        // `あ` は UTF-16 で 1 つ、`𝒂` は 2 つと数える
+        let text = "aあ𝒂b\r\ncd\n";
+        assert_eq!(position_at(text, 8), Position::new(0, 4));
+        assert_eq!(offset_at(text, Position::new(0, 4)), 8);
+        // 行末より後ろの列は \r\n の前に丸める
+        assert_eq!(offset_at(text, Position::new(0, 100)), 9);
+        assert_eq!(position_at(text, 11), Position::new(1, 0));
+        assert_eq!(offset_at(text, Position::new(1, 1)), 12);
+        // 文書の末尾
+        assert_eq!(position_at(text, text.len()), Position::new(2, 0));
+        assert_eq!(position_at(text, 100), Position::new(2, 0));
+        assert_eq!(offset_at(text, Position::new(5, 0)), text.len());
+    }
+
+    /// 次に届く `id` への応答を待つ
+    fn response(client: &Connection, id: i32) -> Response {
+        loop {
+            match client.receiver.recv().unwrap() {
+                Message::Response(response) if response.id == id.into() => return response,
+                _ => {}
+            }
+        }
+    }
+
+    /// 次に届く診断を待つ
+    fn published(client: &Connection) -> PublishDiagnosticsParams {
+        loop {
+            match client.receiver.recv().unwrap() {
+                Message::Notification(notification)
+                    if notification.method == PublishDiagnostics::METHOD =>
+                {
+                    return serde_json::from_value(notification.params).unwrap()
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_option() {
+        let dir = env::temp_dir().join(format!("satysfi-fmt-lsp-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(".editorconfig"),
+            "root = true\n\n[*.saty]\nmax_line_length = 100\n",
+        )
+        .unwrap();
+        let uri = Url::from_file_path(dir.join("main.saty")).unwrap();
+        let options = FormattingOptions {
+            tab_size: 2,
+            insert_spaces: true,
+            ..Default::default()
+        };
+        let option = option(&uri, &options);
+        fs::remove_dir_all(&dir).unwrap();
+        let option = option.unwrap();
+        // CLI と同じ設定になる
+        assert!(!option.command_args_space);
+        assert_eq!(option.row_length, 100);
+        assert_eq!(option.indent_space, 2);
+        assert_eq!(option.source_kind, SourceKind::Saty);
+    }
+
+    #[test]
+    fn test_formatting() {
+        let (server, client) = Connection::memory();
+        let server = thread::spawn(move || serve(&server).map_err(|err| err.to_string()));
+
+        let request = Request::new(
+            1.into(),
+            Initialize::METHOD.to_string(),
+            InitializeParams::default(),
+        );
+        client.sender.send(request.into()).unwrap();
+        assert!(response(&client, 1).error.is_none());
+        let notification = Notification::new(Initialized::METHOD.to_string(), InitializedParams {});
+        client.sender.send(notification.into()).unwrap();
+
+        let uri = Url::parse("file:///tmp/main.saty").unwrap();
+        let text = "let x  =  1\nin\n\ndocument(||)'<>\n";
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                uri.clone(),
+                "satysfi".to_string(),
+                0,
+                text.into(),
+            ),
+        };
+        let notification = Notification::new(DidOpenTextDocument::METHOD.to_string(), params);
+        client.sender.send(notification.into()).unwrap();
+
+        let params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier::new(uri),
+            options: FormattingOptions {
+                tab_size: 4,
+                insert_spaces: true,
+                ..Default::default()
+            },
+            work_done_progress_params: Default::default(),
+        };
+        let request = Request::new(2.into(), Formatting::METHOD.to_string(), params);
+        client.sender.send(request.into()).unwrap();
+        let edits: Vec<TextEdit> =
+            serde_json::from_value(response(&client, 2).result.unwrap()).unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].new_text, "let x = 1\nin\n\ndocument(||)'<>\n");
+
+        // 引数を読めない request にはエラーを返し、サーバは終了しない
+        let params = serde_json::json!({ "unknown": 1 });
+        let request = Request::new(3.into(), Formatting::METHOD.to_string(), params);
+        client.sender.send(request.into()).unwrap();
+        let error = response(&client, 3).error.unwrap();
+        assert_eq!(error.code, ErrorCode::InvalidParams as i32);
+
+        // 閉じた文書の診断は消す
+        let uri = Url::parse("file:///tmp/broken.saty").unwrap();
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                uri.clone(),
+                "satysfi".to_string(),
+                0,
+                "document(||)'<\n    +p {\n>\n".into(),
+            ),
+        };
+        let notification = Notification::new(DidOpenTextDocument::METHOD.to_string(), params);
+        client.sender.send(notification.into()).unwrap();
+        assert_eq!(published(&client).diagnostics.len(), 1);
+        let params = DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+        };
+        let notification = Notification::new(DidCloseTextDocument::METHOD.to_string(), params);
+        client.sender.send(notification.into()).unwrap();
+        let params = published(&client);
+        assert_eq!(params.uri, uri);
+        assert!(params.diagnostics.is_empty());
+
+        let request = Request::new(4.into(), Shutdown::METHOD.to_string(), ());
+        client.sender.send(request.into()).unwrap();
+        assert!(response(&client, 4).error.is_none());
+        let notification = Notification::new(Exit::METHOD.to_string(), ());
+        client.sender.send(notification.into()).unwrap();
+        assert_eq!(server.join().unwrap(), Ok(()));
+    }
+}
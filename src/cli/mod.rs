@@ -0,0 +1,5 @@
+pub mod config;
+pub mod diff;
+pub mod editorconfig;
+pub mod files;
+pub mod lsp;
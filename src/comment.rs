@@ -1,14 +1,90 @@
+use crate::pretty::line_width;
 use satysfi_parser::{Cst, CstText, Rule, Span};
 use std::collections::VecDeque;
+use std::ops::Range;
 
 #[derive(Clone)]
 pub struct Comment {
     pub text: String,
     pub span: Span,
+    pub kind: CommentKind,
+    /// コメントを含む最も内側の要素
+    pub parent: Option<(Rule, Span)>,
+    /// コメントが付いている親の子要素
+    pub node: Option<Span>,
+}
+
+/// コメントがどの要素に付いているか
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommentKind {
+    /// 独立した行にあり、直後の要素に付く
+    Leading,
+    /// 要素と同じ行の末尾にあり、その要素に付く
+    Trailing,
+    /// 直後に要素が無く、親の末尾 (閉じ括弧の直前など) に独立した行として残る
+    Dangling,
+}
+
+/// format を制御するコメント (`% satysfi-fmt: off` など)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Directive {
+    /// ここから `on` まで format しない
+    Off,
+    /// `off` で止めた format を再開する
+    On,
+    /// 次の要素だけ format しない
+    Skip,
+}
+
+impl Comment {
+    /// コメントが format を制御する指示であれば、その種類を返す
+    pub fn directive(&self) -> Option<Directive> {
+        let body = self.text.trim_start_matches('%').trim();
+        let body = body.strip_prefix("satysfi-fmt:")?;
+        match body.trim() {
+            "off" => Some(Directive::Off),
+            "on" => Some(Directive::On),
+            "skip" => Some(Directive::Skip),
+            _ => None,
+        }
+    }
+}
+
+/// `% satysfi-fmt: off` から `% satysfi-fmt: on` までの範囲を返す
+/// `on` が無い場合はファイルの末尾までとする
+pub fn get_verbatim_ranges(comments: &VecDeque<Comment>, len: usize) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    let mut off = None;
+    for comment in comments.iter() {
+        match (comment.directive(), off) {
+            (Some(Directive::Off), None) => off = Some(comment.span.start),
+            (Some(Directive::On), Some(start)) => {
+                ranges.push(start..comment.span.end);
+                off = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = off {
+        ranges.push(start..len);
+    }
+    ranges
+}
+
+/// `% satysfi-fmt: skip` の直後の位置を返す
+pub fn get_skip_positions(comments: &VecDeque<Comment>) -> Vec<usize> {
+    comments
+        .iter()
+        .filter(|comment| comment.directive() == Some(Directive::Skip))
+        .map(|comment| comment.span.end)
+        .collect()
 }
 
 pub fn get_comments(csttext: &CstText) -> VecDeque<Comment> {
     let mut comments = VecDeque::new();
+    // 文字列リテラルなど、% がコメントの開始にならない範囲
+    let mut literals = vec![];
+    get_literal_spans(&csttext.cst, &mut literals);
     // 全ての行を確認する
     for (index, line) in csttext.lines.iter().enumerate() {
         if index == 0 {
@@ -21,19 +97,31 @@ pub fn get_comments(csttext: &CstText) -> VecDeque<Comment> {
             // @require, @import の行ではコメントではない
             continue;
         };
-        if let Some(inner) = text.find('%') {
-            // check whether percent is escaped
-            let is_escaped = {
-                let text_removed_backslash = text[..inner].trim_matches('\\');
-                (text[..inner].len() - text_removed_backslash.len()) % 2 == 1
+        // エスケープされておらず、リテラルの中でもない最初の % からがコメント
+        let inner = text
+            .match_indices('%')
+            .map(|(inner, _)| inner)
+            .find(|&inner| {
+                let in_literal = literals
+                    .iter()
+                    .any(|span| span.start <= start + inner && start + inner < span.end);
+                !in_literal && !is_escaped(&text[..inner])
+            });
+        if let Some(inner) = inner {
+            // 行末の改行 (\r\n を含む) はコメントに含めない
+            let comment = format!("% {}", &text[inner + 1..].trim());
+            // 同じ行のコメントより前に何かあれば行末のコメント
+            let kind = if text[..inner].trim().is_empty() {
+                CommentKind::Leading
+            } else {
+                CommentKind::Trailing
             };
-            if is_escaped {
-                continue;
-            }
-            let comment = format!("% {}", &text[inner + 1..].trim_start());
 
             comments.push_back(Comment {
                 text: comment,
+                kind,
+                parent: None,
+                node: None,
                 // 行内部の開始位置を足す
                 span: Span {
                     start: start + inner,
@@ -45,66 +133,272 @@ pub fn get_comments(csttext: &CstText) -> VecDeque<Comment> {
     comments
 }
 
-fn check_comment(cst: &Cst, comment: &Comment) -> bool {
-    // headers は例外
-    let inner_contain_comment = cst
+/// 直前の backslash の数が奇数なら、続く文字はエスケープされている
+fn is_escaped(before: &str) -> bool {
+    (before.len() - before.trim_end_matches('\\').len()) % 2 == 1
+}
+
+/// 中身をそのまま扱うリテラルの範囲を集める
+fn get_literal_spans(cst: &Cst, spans: &mut Vec<Span>) {
+    match cst.rule {
+        Rule::const_string | Rule::horizontal_escaped_char | Rule::pkgname => spans.push(cst.span),
+        _ => cst
+            .inner
+            .iter()
+            .for_each(|inner| get_literal_spans(inner, spans)),
+    }
+}
+
+/// コメントを、それを含む最も内側の要素 (親) の子要素に付ける
+/// 同じ行で要素の後にあるものは直前の要素に、独立した行にあるものは直後の要素に付け、
+/// 直後の要素が無いもの (閉じ括弧の直前など) は親の末尾に残るものとして直前の要素の後に置く
+/// horizontal や math の中では文字の間の空白が変わらないよう、行末のコメントも独立した行に置く
+pub fn attach_comments(cst: &Cst, comments: &mut VecDeque<Comment>) {
+    for comment in comments.iter_mut() {
+        let parent = innermost(cst, comment);
+        let before = parent
+            .inner
+            .iter()
+            .rev()
+            .find(|inner| inner.span.end <= comment.span.start);
+        let after = parent
+            .inner
+            .iter()
+            .find(|inner| comment.span.end <= inner.span.start);
+        let trailing = comment.kind == CommentKind::Trailing && !is_inline_context(parent.rule);
+        let (kind, node) = match (before, after) {
+            (Some(before), _) if trailing => (CommentKind::Trailing, Some(before.span)),
+            (_, Some(after)) => (CommentKind::Leading, Some(after.span)),
+            (before, None) => (CommentKind::Dangling, before.map(|before| before.span)),
+        };
+        comment.kind = kind;
+        comment.node = node;
+        comment.parent = Some((parent.rule, parent.span));
+    }
+}
+
+/// コメントを含み、子要素にはコメントを含まない要素
+fn innermost<'a>(cst: &'a Cst, comment: &Comment) -> &'a Cst {
+    // headers は例外 (header の間のコメントは headers の後に置く)
+    match cst
         .inner
         .iter()
-        .any(|inner_cst| inner_cst.rule != Rule::headers && inner_cst.span.contains(&comment.span));
-    let contain_comment = cst.span.contains(&comment.span);
-    // コメントを含みかつコメントが内部の要素に含まれていない場合出力する
-    contain_comment && !inner_contain_comment && cst.rule != Rule::headers
-}
-
-/// csttext にコメントを追加して組み直す関数
-pub fn csttext_insert_comments(csttext: CstText) -> CstText {
-    let mut comments = get_comments(&csttext);
-    if let Some(comment) = comments.pop_front() {
-        comments.push_front(comment.clone());
-        check_comment(&csttext.cst, &comment);
-        let mut csttext = csttext;
-        cst_insert_comment(&mut csttext.cst, &mut comments);
-        csttext
-    } else {
-        csttext
+        .find(|inner| inner.rule != Rule::headers && inner.span.contains(&comment.span))
+    {
+        Some(inner) => innermost(inner, comment),
+        None => cst,
     }
 }
 
-/// コメントの挿入をDFSで行う
-fn cst_insert_comment(cst: &mut Cst, comments: &mut VecDeque<Comment>) {
-    let mut insert_comment = vec![];
-    for comment in comments.iter() {
-        let flag = check_comment(cst, comment);
-
-        if flag {
-            #[cfg(debug_assertions)]
-            println!("cst: {:?}, insert-comment: {:?}", cst.rule, comment.text);
-            insert_comment.push(Cst {
-                rule: Rule::comments,
-                inner: vec![],
-                span: comment.span,
-            });
+/// `cst` を親とするコメント
+pub fn attached_comments<'a>(
+    comments: &'a VecDeque<Comment>,
+    cst: &Cst,
+) -> impl Iterator<Item = &'a Comment> {
+    let parent = Some((cst.rule, cst.span));
+    let start = comments.partition_point(|comment| comment.span.start < cst.span.start);
+    let end = comments.partition_point(|comment| comment.span.start < cst.span.end);
+    comments
+        .range(start..end)
+        .filter(move |comment| comment.parent == parent)
+}
+
+/// `output` の `from` 以降で、子要素を format した文字列 `node` が置かれた範囲を探す
+/// 子要素を深いインデントで format し直していても見つかるよう、最初と最後の行で探す
+pub fn find_node_output(output: &str, node: &str, from: usize) -> Option<Range<usize>> {
+    let node = node.trim();
+    let first = node.lines().next()?.trim_end();
+    let last = node.lines().last()?.trim_start();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let mut from = from;
+    while let Some(index) = output.get(from..)?.find(first) {
+        let start = from + index;
+        let end = if node.contains('\n') {
+            let rest = start + first.len();
+            rest + output[rest..].find(last)? + last.len()
+        } else {
+            start + first.len()
+        };
+        // 単語の途中で一致したものは除く
+        let inside_word = (first.starts_with(is_word) && output[..start].ends_with(is_word))
+            || (last.ends_with(is_word) && output[end..].starts_with(is_word));
+        if !inside_word {
+            return Some(start..end);
         }
+        from = start + first.chars().next()?.len_utf8();
     }
+    None
+}
 
-    for inner_cst in cst.inner.iter_mut() {
-        // 再帰的に探索
-        cst_insert_comment(inner_cst, comments);
-    }
+/// 文字の間の空白に意味がある要素
+pub fn is_inline_context(rule: Rule) -> bool {
+    matches!(
+        rule,
+        Rule::horizontal_single
+            | Rule::horizontal_list
+            | Rule::horizontal_bullet_list
+            | Rule::horizontal_bullet
+            | Rule::horizontal_text
+            | Rule::cmd_text_arg
+            | Rule::math_text
+            | Rule::math_list
+            | Rule::math_single
+            | Rule::math_token
+    )
+}
 
-    if !insert_comment.is_empty() {
-        insert_comment.iter().for_each(|comment| {
-            cst.inner.push(Cst {
-                rule: Rule::comments,
-                inner: vec![],
-                span: comment.span,
-            });
-            // insert した回数分減らせる
-            // comment は前から順番に並んでいる
-            comments.pop_front();
+/// format 後の出力で、連続する行の行末のコメントの開始位置を揃える
+/// * `comments` - 行末に置いたコメント
+/// * `tab_width` - 位置を揃えるときのタブ 1 つの幅
+/// * `raw` - format せずに出力した範囲 (`output` での位置)。ここに掛かる行は揃えない
+pub fn align_trailing_comments(
+    output: &str,
+    comments: &[String],
+    tab_width: usize,
+    raw: &[Range<usize>],
+) -> String {
+    let mut rest = comments
+        .iter()
+        .map(|comment| format!(" {comment}"))
+        .collect::<Vec<_>>();
+    // 行と、行末のコメントの開始位置
+    let mut lines: Vec<(String, Option<usize>)> = vec![];
+    let mut start = 0;
+    for line in output.split('\n') {
+        let span = start..start + line.len();
+        start = span.end + 1;
+        let is_raw = raw
+            .iter()
+            .any(|range| range.start <= span.end && span.start < range.end);
+        let index = rest.iter().position(|comment| {
+            line.strip_suffix(comment.as_str())
+                .is_some_and(|code| !code.trim().is_empty())
         });
-        cst.inner.sort_by(|a, b| a.span.start.cmp(&b.span.start))
+        match index {
+            Some(index) if !is_raw => {
+                let comment = rest.remove(index);
+                lines.push((line.to_string(), Some(line.len() - comment.len() + 1)));
+            }
+            _ => lines.push((line.to_string(), None)),
+        }
+    }
+    align_comments(&mut lines, tab_width);
+    lines
+        .into_iter()
+        .map(|(line, _)| line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 行末のコメントが連続する行で、コメントの開始位置を最も長い行に揃える
+fn align_comments(lines: &mut [(String, Option<usize>)], tab_width: usize) {
+    let text_width = |text: &str| line_width(text, tab_width);
+    let mut start = 0;
+    while start < lines.len() {
+        let end = lines[start..]
+            .iter()
+            .position(|(_, position)| position.is_none())
+            .map_or(lines.len(), |len| start + len);
+        if end - start > 1 {
+            let run = &mut lines[start..end];
+            let column = run
+                .iter()
+                .map(|(line, position)| text_width(line[..position.unwrap()].trim_end()))
+                .max()
+                .unwrap_or(0);
+            for (line, position) in run.iter_mut() {
+                let (code, comment) = line.split_at(position.unwrap());
+                let code = code.trim_end();
+                let padding = " ".repeat(column - text_width(code) + 1);
+                *line = format!("{code}{padding}{comment}");
+            }
+        }
+        start = end.max(start + 1);
+    }
+}
+
+/// 独立した行のコメントが連続する部分を段落として、`width` に収まるよう折り返す
+/// 幅を超える行を含む段落だけを対象にし、コードの例や箇条書きに見える行はそのまま残す
+/// * `raw` - format せずに出力した範囲 (`output` での位置)。ここに掛かる行は折り返さない
+pub fn wrap_comments(output: &str, width: usize, tab_width: usize, raw: &[Range<usize>]) -> String {
+    let mut lines: Vec<String> = vec![];
+    // 折り返し待ちの段落 (インデントと本文の行)
+    let mut paragraph: Option<(String, Vec<String>)> = None;
+    let mut start = 0;
+    for line in output.split('\n') {
+        let span = start..start + line.len();
+        start = span.end + 1;
+        let is_raw = raw
+            .iter()
+            .any(|range| range.start <= span.end && span.start < range.end);
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let body = line
+            .trim_start()
+            .strip_prefix("% ")
+            .filter(|body| !is_raw && is_prose(body));
+        match (body, &mut paragraph) {
+            (Some(body), Some((current, bodies))) if current == indent => {
+                bodies.push(body.to_string());
+                continue;
+            }
+            _ => {}
+        }
+        if let Some((indent, bodies)) = paragraph.take() {
+            lines.extend(fill_paragraph(&indent, &bodies, width, tab_width));
+        }
+        match body {
+            Some(body) => paragraph = Some((indent.to_string(), vec![body.to_string()])),
+            None => lines.push(line.to_string()),
+        }
+    }
+    if let Some((indent, bodies)) = paragraph {
+        lines.extend(fill_paragraph(&indent, &bodies, width, tab_width));
+    }
+    lines.join("\n")
+}
+
+/// 折り返してよい文章の行か
+fn is_prose(body: &str) -> bool {
+    let first = match body.split_whitespace().next() {
+        Some(first) => first,
+        None => return false,
+    };
+    let is_list = matches!(first, "-" | "*" | "+")
+        || first
+            .strip_suffix(|c| c == '.' || c == ')')
+            .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()));
+    // 空白で字下げされた行はコードの例とみなす
+    let is_code = body.starts_with(char::is_whitespace);
+    !is_list && !is_code && !body.starts_with("satysfi-fmt:")
+}
+
+/// 段落の単語を `width` に収まるよう詰め直す
+fn fill_paragraph(indent: &str, bodies: &[String], width: usize, tab_width: usize) -> Vec<String> {
+    let text_width = |text: &str| line_width(text, tab_width);
+    let prefix = format!("{indent}% ");
+    if bodies
+        .iter()
+        .all(|body| text_width(&prefix) + text_width(body) <= width)
+    {
+        return bodies
+            .iter()
+            .map(|body| format!("{prefix}{body}"))
+            .collect();
+    }
+    let mut lines = vec![];
+    let mut line = prefix.clone();
+    for word in bodies.iter().flat_map(|body| body.split_whitespace()) {
+        if line.len() > prefix.len() && text_width(&line) + 1 + text_width(word) > width {
+            lines.push(line);
+            line = prefix.clone();
+        }
+        if line.len() > prefix.len() {
+            line += " ";
+        }
+        line += word;
     }
+    lines.push(line);
+    lines
 }
 
 /// コメントを文字列化する関数
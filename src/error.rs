@@ -0,0 +1,194 @@
+use std::fmt;
+use unicode_width::UnicodeWidthChar;
+
+/// format に失敗したときのエラー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// parse に失敗した
+    /// * `line`, `column` - parser が報告した位置
+    /// * `expected` - その位置で期待されていた規則
+    Parse {
+        line: usize,
+        column: usize,
+        expected: Vec<String>,
+    },
+    /// 書きかけの構文 (parser の dummy 規則) が含まれていた
+    /// * `rule` - 見つかった規則の名前
+    /// * `start`, `end` - 該当箇所の byte 位置
+    /// * `line`, `column` - 該当箇所の開始位置
+    Incomplete {
+        rule: String,
+        start: usize,
+        end: usize,
+        line: usize,
+        column: usize,
+    },
+    /// format した結果が元の入力と同じ構造にならなかった
+    /// * `rule` - 最初に異なっていた要素の規則の名前
+    /// * `line`, `column` - 元の入力での該当箇所の開始位置
+    NotEquivalent {
+        rule: String,
+        line: usize,
+        column: usize,
+    },
+    /// format した結果を parse できなかった
+    /// * `line`, `column` - format した結果の中で parse に失敗した位置
+    InvalidOutput { line: usize, column: usize },
+    /// format した結果をもう一度 format すると変わった
+    /// * `line` - 最初に異なっていた行 (1 から数える)
+    /// * `first`, `second` - 1 回目と 2 回目の format でのその行
+    NotIdempotent {
+        line: usize,
+        first: String,
+        second: String,
+    },
+}
+
+impl FormatError {
+    /// コンパイラ風の診断メッセージを返す
+    /// 該当箇所のソースを表示し、列の位置に caret を付ける
+    /// * `name` - 表示するファイル名
+    /// * `source` - format しようとした入力
+    pub fn render(&self, name: &str, source: &str) -> String {
+        match self {
+            FormatError::Parse {
+                line,
+                column,
+                expected,
+            } => {
+                let mut output = format!("error: failed to parse\n --> {name}:{line}:{column}\n");
+                output += &render_snippet(source, *line, *column);
+                if !expected.is_empty() {
+                    output += &format!("  = expected {}\n", describe_expected(expected));
+                }
+                output
+            }
+            FormatError::Incomplete {
+                rule, line, column, ..
+            } => {
+                let mut output =
+                    format!("error: incomplete syntax ({rule})\n --> {name}:{line}:{column}\n");
+                output += &render_snippet(source, *line, *column);
+                output
+            }
+            FormatError::NotEquivalent { rule, line, column } => {
+                let mut output = format!(
+                    "error: formatting changed the meaning ({rule})\n --> {name}:{line}:{column}\n"
+                );
+                output += &render_snippet(source, *line, *column);
+                output
+            }
+            FormatError::InvalidOutput { line, column } => format!(
+                "error: formatted output failed to parse\n --> {name} (formatted):{line}:{column}\n"
+            ),
+            FormatError::NotIdempotent {
+                line,
+                first,
+                second,
+            } => {
+                let mut output = format!(
+                    "error: formatting is not idempotent\n --> {name} (formatted):{line}\n"
+                );
+                output += &format!("  = first:  {first}\n  = second: {second}\n");
+                output
+            }
+        }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Parse {
+                line,
+                column,
+                expected,
+            } => {
+                write!(f, "[parse error] line: {line}, column: {column}")?;
+                if !expected.is_empty() {
+                    write!(f, ", expected {}", describe_expected(expected))?;
+                }
+                Ok(())
+            }
+            FormatError::Incomplete {
+                rule, line, column, ..
+            } => write!(
+                f,
+                "[incomplete syntax] line: {line}, column: {column}, found {rule}"
+            ),
+            FormatError::NotEquivalent { rule, line, column } => write!(
+                f,
+                "[not equivalent] line: {line}, column: {column}, found {rule}"
+            ),
+            FormatError::InvalidOutput { line, column } => {
+                write!(f, "[invalid output] line: {line}, column: {column}")
+            }
+            FormatError::NotIdempotent { line, .. } => {
+                write!(f, "[not idempotent] line: {line}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// 該当行とその直前の行を行番号付きで表示し、`column` の位置に caret を付ける
+/// `line`, `column` は 1 から数える
+fn render_snippet(source: &str, line: usize, column: usize) -> String {
+    let lines = source
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .collect::<Vec<_>>();
+    let width = line.to_string().len();
+    let gutter = " ".repeat(width);
+
+    let mut output = format!("{gutter} |\n");
+    for number in line.saturating_sub(1).max(1)..=line {
+        if let Some(text) = lines.get(number - 1) {
+            output += &format!("{number:>width$} | {text}\n");
+        }
+    }
+    // caret の位置を揃えるため、タブはそのまま残す
+    let padding = line
+        .checked_sub(1)
+        .and_then(|index| lines.get(index))
+        .map_or(String::new(), |text| {
+            text.chars()
+                .take(column.saturating_sub(1))
+                .map(|c| match c {
+                    '\t' => "\t".to_string(),
+                    // 全角文字の下には 2 つ空白を置く
+                    _ => " ".repeat(c.width().unwrap_or(0)),
+                })
+                .collect()
+        });
+    output += &format!("{gutter} | {padding}^\n");
+    output
+}
+
+/// parser の期待していた規則を人が読める形にする
+/// `"let"` のような文字列リテラルは `` `let` `` のように表示する
+fn describe_expected(expected: &[String]) -> String {
+    let mut rules = expected
+        .iter()
+        .map(|rule| {
+            match rule
+                .strip_prefix('"')
+                .and_then(|rule| rule.strip_suffix('"'))
+            {
+                Some(literal) => {
+                    let literal = literal.replace("\\\"", "\"").replace("\\\\", "\\");
+                    format!("`{literal}`")
+                }
+                None => rule.clone(),
+            }
+        })
+        .collect::<Vec<_>>();
+    rules.sort();
+    rules.dedup();
+    if rules.len() == 1 {
+        rules.remove(0)
+    } else {
+        format!("one of {}", rules.join(", "))
+    }
+}
@@ -1,9 +1,16 @@
-use super::OptionData;
-use crate::comment::{get_comments, to_comment_string, Comment};
+use super::{IndentStyle, OptionData};
+use crate::comment::{
+    align_trailing_comments, attach_comments, attached_comments, find_node_output, get_comments,
+    get_skip_positions, get_verbatim_ranges, is_inline_context, to_comment_string, wrap_comments,
+    Comment, CommentKind,
+};
+use crate::pretty::{self, Doc};
 use crate::reserved_words::*;
 use itertools::Itertools;
-use satysfi_parser::{Cst, CstText};
-use std::collections::VecDeque;
+use satysfi_parser::{Cst, CstText, Rule};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
 
 pub struct Formatter<'a> {
     pub text: &'a str,
@@ -12,11 +19,35 @@ pub struct Formatter<'a> {
     pub depth: usize,
     pub output: String,
     option: OptionData,
+    /// format せずに元の文字列をそのまま出力する範囲
+    verbatim: Vec<Range<usize>>,
+    /// `% satysfi-fmt: skip` の直後の位置
+    skips: Vec<usize>,
+    /// headers を並べ替えるときに直後の header と一緒に移動するコメントの開始位置
+    header_comments: Vec<usize>,
+    /// `to_string_cst_at` で指定された、要素の開始位置とそれが置かれる列
+    column: Cell<Option<(usize, usize)>>,
+    /// 折り返さずにそのまま出力した文字列 (複数行のリテラルや `off` の範囲)
+    raw: RefCell<Vec<String>>,
+    /// コメントが付いている要素の子要素を format した文字列 (位置で引く)
+    outputs: RefCell<HashMap<(usize, usize), String>>,
+    /// 行末に置いたコメント
+    trailing: RefCell<Vec<String>>,
 }
 
 impl<'a> Formatter<'a> {
     pub fn new(csttext: &'a CstText, option: OptionData) -> Self {
-        let comments = get_comments(csttext);
+        let mut comments = get_comments(csttext);
+        attach_comments(&csttext.cst, &mut comments);
+        let verbatim = get_verbatim_ranges(&comments, csttext.text.len());
+        let skips = get_skip_positions(&comments);
+        let header_comments = if option.sort_headers {
+            get_header_comments(&csttext.text, &csttext.cst, &comments)
+        } else {
+            vec![]
+        };
+        let mut outputs = HashMap::new();
+        comment_nodes(&csttext.cst, &comments, &mut outputs);
         Self {
             text: &csttext.text,
             lines: &csttext.lines,
@@ -24,18 +55,25 @@ impl<'a> Formatter<'a> {
             depth: 0,
             output: String::new(),
             option,
+            verbatim,
+            skips,
+            header_comments,
+            column: Cell::new(None),
+            raw: RefCell::new(vec![]),
+            outputs: RefCell::new(outputs),
+            trailing: RefCell::new(vec![]),
         }
     }
 
     /// 文字列を format して出力する
     /// 前処理後処理もここで行う
     pub fn format(&self, input: &str, cst: &Cst, depth: usize) -> String {
-        let mut output = self.to_string_cst(input, &cst, depth);
-        // 末尾スペースを全て除去
-        output = output.split("\n").map(|line| {
-            line.trim_end()
-        }).collect::<Vec<_>>().join("\n");
+        let mut output = self.format_node(input, cst, depth);
 
+        if !self.option.insert_final_newline {
+            // 末尾の改行を付けない
+            return output.trim_end_matches('\n').to_string();
+        }
         // 末尾に改行がない場合、改行を挿入して終了
         if !output.ends_with('\n') {
             output += "\n";
@@ -43,6 +81,43 @@ impl<'a> Formatter<'a> {
         output
     }
 
+    /// cst の1つの要素を format する
+    /// `depth` は要素が置かれる位置のインデントの深さ
+    pub fn format_node(&self, input: &str, cst: &Cst, depth: usize) -> String {
+        let output = self.to_string_cst(input, cst, depth);
+        let raw = self.raw.take();
+        // 出力の中で raw の文字列が置かれた範囲
+        let raw_ranges = |output: &str| {
+            raw.iter()
+                .flat_map(|text| {
+                    output
+                        .match_indices(text.as_str())
+                        .map(|(start, text)| start..start + text.len())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let output = if self.option.wrap_comments {
+            let raw = raw_ranges(&output);
+            wrap_comments(&output, self.option.row_length, self.option.tab_width, &raw)
+        } else {
+            output
+        };
+        let trailing = self.trailing.take();
+        let output = if self.option.align_trailing_comments {
+            let raw = raw_ranges(&output);
+            align_trailing_comments(&output, &trailing, self.option.tab_width, &raw)
+        } else {
+            output
+        };
+        // 末尾スペースを全て除去
+        output
+            .split("\n")
+            .map(|line| line.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
     /// cst の inner の要素を結合して文字列に変換する関数
     fn to_string_cst_inner(&self, text: &str, cst: &Cst, depth: usize) -> String {
         /*
@@ -55,7 +130,7 @@ impl<'a> Formatter<'a> {
         use satysfi_parser::Rule;
         let csts = cst.inner.clone();
         // 関数内で改行するときはこれを使用する
-        let indent = indent_space(self.option.indent_space, depth);
+        let indent = self.indent(depth);
         let newline = format!("\n{indent}");
         let sep = &match cst.rule {
             Rule::block_cmd | Rule::inline_cmd => " ".to_string(),
@@ -78,6 +153,10 @@ impl<'a> Formatter<'a> {
         };
 
         let output = match cst.rule {
+            Rule::headers if self.option.sort_headers => self.sort_headers(text, &csts, depth),
+            Rule::horizontal_single if self.option.fill_text && !self.has_comments(cst) => {
+                self.fill_horizontal_single(text, &csts, depth)
+            }
             Rule::variant_constructor => {
                 let mut output = String::new();
                 for cst in csts {
@@ -105,16 +184,11 @@ impl<'a> Formatter<'a> {
                             if s.contains('\n') {
                                 // 1つインデントを深くする
                                 let s = self.to_string_cst(text, now_cst, depth + 1);
-                                current
-                                    + " <-"
-                                    + &newline
-                                    + &indent_space(self.option.indent_space, 1)
-                                    + s.trim_start()
+                                current + " <-" + &newline + &self.indent(1) + s.trim_start()
                             } else {
                                 current + " <- " + &s
                             }
                         }
-                        Rule::comments => current + &s,
                         _ => unreachable!(),
                     }
                 })
@@ -124,9 +198,7 @@ impl<'a> Formatter<'a> {
                 let output = csts.iter().fold(String::new(), |current, now_cst| {
                     let s = self.to_string_cst(text, now_cst, depth);
                     if current.is_empty() {
-                        if now_cst.rule != Rule::comments {
-                            cnt += 1;
-                        }
+                        cnt += 1;
                         return s;
                     }
                     match now_cst.rule {
@@ -146,7 +218,6 @@ impl<'a> Formatter<'a> {
                                 current + &s
                             }
                         }
-                        Rule::comments => current + &s,
                         _ => unreachable!(),
                     }
                 });
@@ -176,13 +247,6 @@ impl<'a> Formatter<'a> {
                         Rule::inline_cmd_name => current + " " + &s,
                         Rule::block_cmd_name => current + " " + &s,
                         Rule::type_expr => current + ": " + &s,
-                        Rule::comments => {
-                            if current.ends_with(char::is_whitespace) {
-                                current + &s
-                            } else {
-                                current + &newline + &s
-                            }
-                        }
                         _ => current + " " + &s,
                     }
                 })
@@ -193,69 +257,66 @@ impl<'a> Formatter<'a> {
             | Rule::let_inline_stmt_noctx
             | Rule::let_stmt
             | Rule::let_math_stmt => {
-                csts.iter()
-                    .enumerate()
-                    .fold(String::new(), |current, (index, now_cst)| {
-                        let s = self.to_string_cst(text, now_cst, depth);
-                        let s = if cst.rule == Rule::sig_val_stmt
-                            && now_cst.rule == Rule::bin_operator
-                        {
-                            format!("({s})")
-                        } else {
-                            s
-                        };
-                        if current.is_empty() {
-                            return s;
+                // 文の先頭のキーワードの分だけ右から始まる
+                let keyword = match cst.rule {
+                    Rule::let_block_stmt_ctx | Rule::let_block_stmt_noctx => {
+                        RESERVED_WORD.let_block
+                    }
+                    Rule::let_inline_stmt_ctx | Rule::let_inline_stmt_noctx => {
+                        RESERVED_WORD.let_inline
+                    }
+                    Rule::let_math_stmt => RESERVED_WORD.let_math,
+                    _ => RESERVED_WORD.let_stmt,
+                };
+                let start = self.start_column(cst, depth) + pretty::text_width(keyword) + 1;
+                csts.iter().fold(String::new(), |current, now_cst| {
+                    // 同じ行に続けて置いたときの位置で format する
+                    let s = match now_cst.rule {
+                        Rule::type_expr | Rule::expr if !current.is_empty() => {
+                            let sep = if now_cst.rule == Rule::expr {
+                                " = "
+                            } else {
+                                ": "
+                            };
+                            let column = self.column_after(&(current.clone() + sep), start);
+                            self.to_string_cst_at(text, now_cst, depth, column)
+                        }
+                        _ => self.to_string_cst(text, now_cst, depth),
+                    };
+                    let s = if cst.rule == Rule::sig_val_stmt && now_cst.rule == Rule::bin_operator
+                    {
+                        format!("({s})")
+                    } else {
+                        s
+                    };
+                    if current.is_empty() {
+                        return s;
+                    }
+                    match now_cst.rule {
+                        Rule::var => current + " " + &s,
+                        Rule::block_cmd_name => current + " " + &s,
+                        Rule::bin_operator => current + &format!(" ({s})"),
+                        Rule::type_expr => current + ": " + &s,
+                        Rule::constraint => {
+                            // 1つインデントを深くする
+                            let s = self.to_string_cst(text, now_cst, depth + 1);
+                            current + &newline + &self.indent(1) + &s
                         }
-                        match now_cst.rule {
-                            Rule::var => current + " " + &s,
-                            Rule::block_cmd_name => current + " " + &s,
-                            Rule::bin_operator => current + &format!(" ({s})"),
-                            Rule::type_expr => current + ": " + &s,
-                            Rule::constraint => {
+                        Rule::expr => {
+                            // ブロック定義は例外
+                            if s.starts_with("let")
+                                || (!s.starts_with("'<") && !s.starts_with('{')) && s.contains('\n')
+                            {
                                 // 1つインデントを深くする
                                 let s = self.to_string_cst(text, now_cst, depth + 1);
-                                current + &newline + &indent_space(self.option.indent_space, 1) + &s
-                            }
-                            Rule::expr => {
-                                // 直前がコメント
-                                if index > 0 && csts[index - 1].rule == Rule::comments {
-                                    // 1つインデントを深くする
-                                    let s = self.to_string_cst(text, now_cst, depth + 1);
-                                    current + &s
-                                }
-                                // ブロック定義は例外
-                                else if s.starts_with("let")
-                                    || (!s.starts_with("'<") && !s.starts_with('{'))
-                                        && s.contains('\n')
-                                {
-                                    // 1つインデントを深くする
-                                    let s = self.to_string_cst(text, now_cst, depth + 1);
-                                    current
-                                        + " ="
-                                        + &newline
-                                        + &indent_space(self.option.indent_space, 1)
-                                        + s.trim_start()
-                                } else {
-                                    current + " = " + &s
-                                }
-                            }
-                            Rule::comments => {
-                                if index + 1 < csts.len() && csts[index + 1].rule == Rule::expr {
-                                    // 1つインデントを深くする
-                                    let s = self.to_string_cst(text, now_cst, depth + 1);
-                                    current
-                                        + " ="
-                                        + &newline
-                                        + &indent_space(self.option.indent_space, 1)
-                                        + &s
-                                } else {
-                                    current + &s
-                                }
+                                current + " =" + &newline + &self.indent(1) + s.trim_start()
+                            } else {
+                                current + " = " + &s
                             }
-                            _ => current + " " + &s,
                         }
-                    })
+                        _ => current + " " + &s,
+                    }
+                })
             }
             Rule::math_cmd_expr_arg | Rule::math_cmd_expr_option => {
                 // 高々1つの要素
@@ -269,7 +330,6 @@ impl<'a> Formatter<'a> {
                         Rule::vertical => current + &format!("!{s}"),
                         Rule::expr => current + &format!("!({s})"),
                         Rule::record | Rule::list => current + &format!("!{s}"),
-                        Rule::comments => current + &s,
                         _ => unreachable!(),
                     }
                 })
@@ -304,10 +364,21 @@ impl<'a> Formatter<'a> {
                     }
                     Rule::pat_variant => current + " " + &s,
                     Rule::pat_as => current + " :: " + &s,
-                    Rule::comments => current + &newline + &s,
                     _ => unreachable!(),
                 }
             }),
+            Rule::pat_as => csts.iter().fold(String::new(), |current, now_cst| {
+                let s = self.to_string_cst(text, now_cst, depth);
+                if current.is_empty() {
+                    return s;
+                }
+                match now_cst.rule {
+                    Rule::var => current + " " + RESERVED_WORD.as_stmt + " " + &s,
+                    _ => current + " " + &s,
+                }
+            }),
+            Rule::pat_tuple => self.separated_items(text, cst, depth, ","),
+            Rule::pat_list => self.separated_items(text, cst, depth, ";"),
             Rule::pat_variant => csts.iter().fold(String::new(), |current, now_cst| {
                 let s = self.to_string_cst(text, now_cst, depth);
                 if current.is_empty() {
@@ -322,7 +393,6 @@ impl<'a> Formatter<'a> {
                         }
                     }
                     Rule::variant_name => current + " " + &s,
-                    Rule::comments => current + &newline + &s,
                     _ => unreachable!(),
                 }
             }),
@@ -334,7 +404,6 @@ impl<'a> Formatter<'a> {
                 match now_cst.rule {
                     Rule::type_param => current + " " + &s,
                     Rule::type_record => current + " :: " + &s,
-                    Rule::comments => current + &newline + &s,
                     _ => unreachable!(),
                 }
             }),
@@ -367,25 +436,15 @@ impl<'a> Formatter<'a> {
                         Rule::type_record_unit => {
                             output += &s;
                         }
-                        Rule::comments => {
-                            output += &s;
-                        }
                         _ => unreachable!(),
                     };
                     // 次の要素が存在すれば結合
                     let next = iter.peek();
                     if next != None
-                        && now_cst.rule != Rule::comments
-                        && next.unwrap().rule == Rule::comments
-                    {
-                        output += sep;
-                    } else if next != None
                         && (next.unwrap().rule == Rule::record_unit
                             || next.unwrap().rule == Rule::type_record_unit)
                     {
                         output += sep;
-                    } else if next == None && now_cst.rule == Rule::comments {
-                        output = output.trim_end().to_string();
                     };
                 }
                 output
@@ -418,7 +477,6 @@ impl<'a> Formatter<'a> {
                     match now_cst.rule {
                         Rule::variant_name => current + &s,
                         Rule::type_expr => current + " of " + &s,
-                        Rule::comments => current + &s,
                         _ => unreachable!(),
                     }
                 });
@@ -510,7 +568,6 @@ impl<'a> Formatter<'a> {
                                 _ => unreachable!(),
                             }
                         }
-                        Rule::comments => current + &s,
                         _ => unreachable!(),
                     }
                 });
@@ -529,7 +586,6 @@ impl<'a> Formatter<'a> {
                                 _ => current + &s,
                             }
                         }
-                        Rule::comments => current + &s,
                         _ => current + &s,
                     }
                 });
@@ -569,7 +625,6 @@ impl<'a> Formatter<'a> {
                     }
                     match now_cst.rule {
                         Rule::pattern => current + " " + &s,
-                        Rule::comments => current + &s,
                         _ => current + " -> " + &s,
                     }
                 }),
@@ -581,7 +636,6 @@ impl<'a> Formatter<'a> {
                 match now_cst.rule {
                     Rule::var_ptn => current + " " + &s,
                     Rule::expr => current + " = " + &s,
-                    Rule::comments => current + &s,
                     _ => unreachable!(),
                 }
             }),
@@ -593,7 +647,6 @@ impl<'a> Formatter<'a> {
                 match now_cst.rule {
                     Rule::var => current + " " + &s,
                     Rule::type_expr => current + ": " + &s,
-                    Rule::comments => current + &s,
                     _ => unreachable!(),
                 }
             }),
@@ -601,13 +654,6 @@ impl<'a> Formatter<'a> {
                 let mut output = String::new();
                 for cst in &csts {
                     let s = self.to_string_cst(text, cst, depth);
-                    if cst.rule == Rule::comments {
-                        if !output.ends_with(char::is_whitespace) {
-                            output += &newline;
-                        }
-                        output += &s;
-                        continue;
-                    }
                     if !output.is_empty() {
                         output += sep;
                     }
@@ -631,7 +677,6 @@ impl<'a> Formatter<'a> {
                     | Rule::application
                     | Rule::unary
                     | Rule::variant_constructor => current + " <- " + &s,
-                    Rule::comments => current + &s,
                     _ => unreachable!(),
                 }
             }),
@@ -639,18 +684,39 @@ impl<'a> Formatter<'a> {
                 if csts.is_empty() {
                     return "".to_string();
                 }
-                let first_text = self.to_string_cst(text, &csts[0], depth);
-                let insert_space = first_text != "document";
-                let mut output = first_text;
-                for cst in csts.iter().skip(1) {
-                    let s = self.to_string_cst(text, cst, depth);
-                    if insert_space {
-                        output += " ";
-                    }
-                    output += &s;
+                let start = self.start_column(cst, depth);
+                let first_text = self.to_string_cst_at(text, &csts[0], depth, start);
+                // 1 行に並べたときの位置で引数を format する
+                let mut column = self.column_after(&first_text, start);
+                let args = csts
+                    .iter()
+                    .skip(1)
+                    .map(|cst| {
+                        let s = self.to_string_cst_at(text, cst, depth, column + 1);
+                        column = self.column_after(&s, column + 1);
+                        s
+                    })
+                    .collect::<Vec<_>>();
+                if first_text == "document" {
+                    return first_text + &args.concat();
                 }
-                output
+                if first_text.contains('\n') || args.iter().any(|arg| arg.contains('\n')) {
+                    // 複数行にわたる引数がある場合は、そのまま並べる
+                    return std::iter::once(first_text).chain(args).join(" ");
+                }
+                // 1 行に収まらない場合は引数ごとに改行する
+                let args = args
+                    .into_iter()
+                    .map(|arg| Doc::concat(vec![Doc::line(), Doc::text(arg)]))
+                    .collect();
+                let doc = Doc::group(Doc::concat(vec![
+                    Doc::text(first_text.clone()),
+                    Doc::nest(self.indent(1), Doc::concat(args)),
+                ]));
+                // 1 行の引数は左に寄せても変わらないので、改行しても format し直さない
+                self.render(&doc, depth, start)
             }
+            Rule::tuple => self.separated_items(text, cst, depth, ","),
             Rule::bind_stmt => {
                 // let* ~ in のとき用
                 let output =
@@ -671,33 +737,21 @@ impl<'a> Formatter<'a> {
                                     }
                                 }
                                 Rule::expr => {
-                                    let current =
-                                        if index > 0 && csts[index - 1].rule == Rule::comments {
-                                            current
-                                        } else if s.starts_with("let") || s.contains('\n') {
-                                            current + &newline
-                                        } else {
-                                            current + " "
-                                        };
+                                    let current = if s.starts_with("let") || s.contains('\n') {
+                                        current + &newline
+                                    } else {
+                                        current + " "
+                                    };
                                     if s.starts_with("let") {
                                         current + s.trim_start()
                                     } else if s.contains('\n') {
                                         let s = self.to_string_cst(text, now_cst, depth + 1);
                                         // 1つ深くする
-                                        current
-                                            + &indent_space(self.option.indent_space, 1)
-                                            + s.trim_start()
+                                        current + &self.indent(1) + s.trim_start()
                                     } else {
                                         current + s.trim_start()
                                     }
                                 }
-                                Rule::comments => {
-                                    if current.ends_with(RESERVED_WORD.in_stmt) {
-                                        current + &newline + &s
-                                    } else {
-                                        current + &s
-                                    }
-                                }
                                 _ => current + &s,
                             }
                         });
@@ -706,28 +760,41 @@ impl<'a> Formatter<'a> {
             Rule::type_expr => {
                 let mut iter = csts.into_iter().peekable();
                 let mut now_cst = iter.next().unwrap();
-                let mut output = self.to_string_cst(text, &now_cst, depth);
+                let first = self.to_string_cst(text, &now_cst, depth);
+                // 1 行に収まらない場合は矢印の前で改行するため、矢印ごとに区切って保持する
+                let mut arrows = vec![];
                 while iter.peek() != None {
                     // 次の要素が存在すれば結合
-                    if now_cst.rule == Rule::type_optional {
-                        output += " ?-> ";
+                    let arrow = if now_cst.rule == Rule::type_optional {
+                        "?->"
                     } else {
-                        output += " -> ";
-                    }
+                        "->"
+                    };
                     now_cst = iter.next().unwrap();
 
                     let s = self.to_string_cst(text, &now_cst, depth);
                     match now_cst.rule {
                         Rule::type_prod | Rule::type_optional => {
-                            output += &s;
-                        }
-                        Rule::comments => {
-                            output += &s;
+                            arrows.push(format!("{arrow} {s}"));
                         }
                         _ => unreachable!(),
                     }
                 }
-                output
+                if first.contains('\n') || arrows.iter().any(|arrow| arrow.contains('\n')) {
+                    arrows
+                        .iter()
+                        .fold(first, |current, arrow| current + " " + arrow)
+                } else {
+                    let arrows = arrows
+                        .into_iter()
+                        .map(|arrow| Doc::concat(vec![Doc::line(), Doc::text(arrow)]))
+                        .collect();
+                    let doc = Doc::group(Doc::concat(vec![
+                        Doc::text(first),
+                        Doc::nest(self.indent(1), Doc::concat(arrows)),
+                    ]));
+                    self.render(&doc, depth, self.start_column(cst, depth))
+                }
             }
             Rule::module_stmt => {
                 let mut iter = csts.into_iter().peekable();
@@ -744,7 +811,6 @@ impl<'a> Formatter<'a> {
                         Rule::struct_stmt => {
                             output += " = ";
                         }
-                        Rule::comments => {}
                         _ => unreachable!(),
                     }
                     output += &s;
@@ -762,8 +828,6 @@ impl<'a> Formatter<'a> {
                             // 改行の制御
                             let current = if current.is_empty() || current.ends_with(check) {
                                 current
-                            } else if index > 0 && csts[index - 1].rule == Rule::comments {
-                                current
                             } else if index > 0 && csts[index - 1].rule != now_cst.rule {
                                 // ルールの切り替わり位置
                                 current + "\n" + &newline
@@ -778,7 +842,6 @@ impl<'a> Formatter<'a> {
                                     Rule::let_stmt | Rule::let_rec_stmt => {
                                         current + "\n" + &newline
                                     }
-                                    Rule::comments => current,
                                     _ => {
                                         // 基本的に改行する
                                         current + &newline
@@ -787,7 +850,6 @@ impl<'a> Formatter<'a> {
                             };
                             match now_cst.rule {
                                 Rule::let_stmt | Rule::let_rec_stmt => current + &s,
-                                Rule::comments => current + &s,
                                 Rule::bind_stmt => current + &s,
                                 _ => current + &s,
                             }
@@ -801,7 +863,6 @@ impl<'a> Formatter<'a> {
                         Rule::sig_val_stmt | Rule::sig_type_stmt => current + &newline + &s,
                         Rule::module_name => current + " " + &s,
                         Rule::struct_stmt => current + "= " + RESERVED_WORD.struct_stmt + &s,
-                        Rule::comments => current + &s,
                         _ => current + &s,
                     }
                 });
@@ -827,14 +888,12 @@ impl<'a> Formatter<'a> {
                         } else {
                             current + &s
                         }
-                    } else if now_cst.rule == Rule::comments {
-                        format!("{}{newline}{s}", current.trim_end())
                     } else {
                         current + &s
                     }
                 });
 
-                // コメントが末尾にあるとき余計な改行が残ってしまうので削除
+                // 前後の空白の改行が残ってしまうので削除
                 output.trim().to_string()
             }
             Rule::preamble => csts.iter().fold(String::new(), |current, now_cst| {
@@ -854,13 +913,8 @@ impl<'a> Formatter<'a> {
             Rule::horizontal_list => csts.iter().fold("|".to_string(), |current, now_cst| {
                 // 実装しているが使わない
                 let s = self.to_string_cst(text, now_cst, depth);
-                let flag = now_cst.rule == Rule::comments;
-                if flag {
-                    current + &s
-                } else if s.is_empty() {
+                if s.is_empty() {
                     current
-                } else if now_cst.rule == Rule::comments {
-                    current + &s
                 } else {
                     current + " " + &s + sep
                 }
@@ -869,15 +923,10 @@ impl<'a> Formatter<'a> {
                 .iter()
                 .fold(String::new(), |current, now_cst| {
                     let s = self.to_string_cst(text, now_cst, depth);
-                    let flag = now_cst.rule == Rule::comments;
-                    if flag {
-                        current + &s
-                    } else if current.is_empty() {
+                    if current.is_empty() {
                         s + sep
                     } else if s.is_empty() {
                         current
-                    } else if now_cst.rule == Rule::comments {
-                        current + &s
                     } else {
                         current + &s + sep
                     }
@@ -885,19 +934,23 @@ impl<'a> Formatter<'a> {
                 .trim_end()
                 .to_string(),
             Rule::block_cmd | Rule::inline_cmd => {
+                let start = self.start_column(cst, depth);
                 csts.iter().fold(String::new(), |current, now_cst| {
-                    let s = self.to_string_cst(text, now_cst, depth);
-                    if current.is_empty() {
-                        s
-                    } else if s.is_empty() {
-                        current
-                    } else if current.ends_with(&newline) {
-                        current + &s
-                    } else if now_cst.rule == Rule::cmd_text_arg && !self.option.command_args_space
+                    let prefix = if current.is_empty()
+                        || current.ends_with(&newline)
+                        || (now_cst.rule == Rule::cmd_text_arg && !self.option.command_args_space)
                     {
-                        current + &s
+                        current.clone()
                     } else {
-                        current + sep + &s
+                        current.clone() + sep
+                    };
+                    // 前の引数に続けて置いたときの位置で format する
+                    let column = self.column_after(&prefix, start);
+                    let s = self.to_string_cst_at(text, now_cst, depth, column);
+                    if s.is_empty() {
+                        current
+                    } else {
+                        prefix + &s
                     }
                 })
             }
@@ -915,8 +968,6 @@ impl<'a> Formatter<'a> {
                         current + &s
                     } else if last_token.starts_with("\\") {
                         current + sep + &s
-                    } else if now_cst.rule == Rule::comments {
-                        format!("{}{newline}{s}", current.trim_end())
                     } else if s.starts_with(char::is_alphabetic)
                         && current.ends_with(char::is_alphabetic)
                     {
@@ -956,10 +1007,11 @@ impl<'a> Formatter<'a> {
                         current + &s
                     } else {
                         // 複数行の改行を省略して1行にする
-                        let start = now_cst.span.start;
+                        // 行頭から始まる要素の行も数えるため、start と同じ位置も含める
+                        let start = self.start_with_comments(now_cst);
                         let mut cnt = 0;
                         for &value in self.lines.iter() {
-                            if line_index < value && value < start {
+                            if line_index < value && value <= start {
                                 cnt += 1;
                             }
                         }
@@ -971,23 +1023,39 @@ impl<'a> Formatter<'a> {
                     output.trim_end().to_string()
                 })
             }
-            Rule::dyadic_expr => csts.iter().fold(String::new(), |current, now_cst| {
-                let s = self.to_string_cst(text, now_cst, depth);
-                let output = if current.is_empty() {
-                    s
-                } else if s.is_empty() {
-                    current
-                } else if current.ends_with(&newline) {
-                    current + &s
-                } else {
-                    if now_cst.rule == Rule::bin_operator && s.trim() == "|>" {
+            Rule::dyadic_expr => {
+                let start = self.start_column(cst, depth);
+                let mut pipe = false;
+                csts.iter().fold(String::new(), |current, now_cst| {
+                    // 同じ行に続けて置いたときの位置で format する
+                    let column = if current.is_empty() {
+                        start
+                    } else {
+                        self.column_after(&current, start) + 1
+                    };
+                    // `|>` の後は 1 つ深いインデントの行に置かれる
+                    let s = if pipe {
+                        self.to_string_cst_at(text, now_cst, depth + 1, column)
+                    } else {
+                        self.to_string_cst_at(text, now_cst, depth, column)
+                    };
+                    pipe = now_cst.rule == Rule::bin_operator && s.trim() == "|>";
+                    let output = if current.is_empty() {
+                        s
+                    } else if s.is_empty() {
+                        current
+                    } else if current.ends_with(&newline) {
                         current + &s
                     } else {
-                        current + sep + &s
-                    }
-                };
-                output
-            }),
+                        if now_cst.rule == Rule::bin_operator && s.trim() == "|>" {
+                            current + &s
+                        } else {
+                            current + sep + &s
+                        }
+                    };
+                    output
+                })
+            }
             _ => {
                 csts.iter().fold(String::new(), |current, now_cst| {
                     let s = self.to_string_cst(text, now_cst, depth);
@@ -1014,30 +1082,469 @@ impl<'a> Formatter<'a> {
         output
     }
 
+    /// `@stage` 以外の header を `@require`, `@import` の順に、それぞれ名前順に並べる
+    /// 同じ header は最初のものだけを残し、直前のコメントは header と一緒に移動する
+    fn sort_headers(&self, text: &str, csts: &[Cst], depth: usize) -> String {
+        // (`@require` なら 0 で `@import` なら 1, 名前, 直前のコメント, header)
+        let mut headers: Vec<(usize, String, String, String)> = vec![];
+        let mut others = String::new();
+        let mut comments = self
+            .comments
+            .iter()
+            .filter(|comment| self.header_comments.contains(&comment.span.start))
+            .peekable();
+        for cst in csts {
+            let mut comment = String::new();
+            while let Some(now) = comments.next_if(|now| now.span.start < cst.span.start) {
+                comment += &(to_comment_string(now.text.clone()) + "\n");
+            }
+            let output = self.to_string_cst(text, cst, depth);
+            let group = match cst.rule {
+                Rule::header_require => 0,
+                Rule::header_import => 1,
+                _ => {
+                    others += &(comment + &output);
+                    continue;
+                }
+            };
+            // 行末のコメントは名前に含めない
+            let name = cst
+                .inner
+                .iter()
+                .find(|inner| inner.rule == Rule::pkgname)
+                .map_or("", |pkgname| &text[pkgname.span.start..pkgname.span.end]);
+            let name = name
+                .split('%')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string();
+            match headers
+                .iter_mut()
+                .find(|header| header.0 == group && header.1 == name)
+            {
+                // 取り除いた header のコメントは残した header に付ける
+                Some(header) => header.2 += &comment,
+                None => headers.push((group, name, comment, output)),
+            }
+        }
+        headers.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        headers
+            .into_iter()
+            .fold(others, |output, (_, _, comment, header)| {
+                output + &comment + &header
+            })
+    }
+
+    /// 行内のテキストを row_length に収まるように詰めて折り返す
+    /// 空白の位置と全角文字の間で改行し、コマンドや数式、エスケープした文字の途中では改行しない
+    fn fill_horizontal_single(&self, text: &str, csts: &[Cst], depth: usize) -> String {
+        // (直前の単語との間で改行しないときに挟む文字列, 単語)
+        let mut words: Vec<(&str, String)> = vec![];
+        // 直前に空白があったか
+        let mut space = false;
+        for cst in csts {
+            let pieces = match cst.rule {
+                Rule::regular_text => text[cst.span.start..cst.span.end]
+                    .chars()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>(),
+                _ => vec![self.to_string_cst(text, cst, depth)],
+            };
+            for piece in pieces {
+                if piece.trim().is_empty() {
+                    space = true;
+                    continue;
+                }
+                match words.last_mut() {
+                    Some(_) if space => words.push((" ", piece)),
+                    Some((_, word)) => {
+                        let before = word.chars().last().unwrap_or(' ');
+                        let after = piece.chars().next().unwrap_or(' ');
+                        if pretty::can_break_between(before, after) {
+                            words.push(("", piece));
+                        } else {
+                            *word += &piece;
+                        }
+                    }
+                    None => words.push(("", piece)),
+                }
+                space = false;
+            }
+        }
+        pretty::fill(
+            &words,
+            self.option.row_length,
+            &self.indent(depth),
+            self.option.tab_width,
+        )
+    }
+
+    /// tuple や list の pattern のような括弧の中の要素を `sep` で区切って並べる
+    /// 1 行に収まらない場合は要素ごとに改行する
+    fn separated_items(&self, text: &str, cst: &Cst, depth: usize, sep: &str) -> String {
+        // 開き括弧の分だけ右から始まる
+        let start = self.start_column(cst, depth) + 1;
+        let mut column = start;
+        let items = cst
+            .inner
+            .iter()
+            .map(|inner| {
+                let s = self.to_string_cst_at(text, inner, depth, column);
+                column = self.column_after(&s, column) + sep.len() + 1;
+                s
+            })
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        if items.iter().any(|item| item.contains('\n')) {
+            // コメントや複数行の要素を含む場合は、そのまま結合する
+            let newline = format!("\n{}", self.indent(depth));
+            return items.into_iter().fold(String::new(), |current, s| {
+                if current.is_empty() || current.ends_with(&newline) {
+                    current + &s
+                } else {
+                    current + sep + " " + &s
+                }
+            });
+        }
+        if items.is_empty() {
+            return String::new();
+        }
+        let doc = Doc::group(Doc::concat(vec![
+            Doc::nest(
+                self.indent(1),
+                Doc::concat(vec![
+                    Doc::softline(),
+                    Doc::join(
+                        items.into_iter().map(Doc::text).collect(),
+                        Doc::concat(vec![Doc::text(sep), Doc::line()]),
+                    ),
+                ]),
+            ),
+            Doc::softline(),
+        ]));
+        self.render(&doc, depth, start)
+    }
+
+    /// `depth` 段分のインデント
+    pub fn indent(&self, depth: usize) -> String {
+        match self.option.indent_style {
+            IndentStyle::Space => " ".repeat(self.option.indent_space * depth),
+            IndentStyle::Tab => "\t".repeat(depth),
+        }
+    }
+
+    /// 文書を行の `column` の位置から置いたものとして文字列にする
+    /// 改行したときは `depth` 段のインデントを基準にする
+    fn render(&self, doc: &Doc, depth: usize, column: usize) -> String {
+        pretty::render(
+            doc,
+            self.option.row_length,
+            &self.indent(depth),
+            column,
+            self.option.tab_width,
+        )
+    }
+
+    /// 行の `column` の位置から始まるものとして cst を format する
+    fn to_string_cst_at(&self, text: &str, cst: &Cst, depth: usize, column: usize) -> String {
+        let saved = self.column.replace(Some((cst.span.start, column)));
+        let output = self.to_string_cst(text, cst, depth);
+        self.column.set(saved);
+        output
+    }
+
+    /// cst の先頭が置かれる列
+    /// `to_string_cst_at` で指定されていなければ `depth` 段のインデントの位置とする
+    fn start_column(&self, cst: &Cst, depth: usize) -> usize {
+        match self.column.get() {
+            Some((start, column)) if start == cst.span.start => column,
+            _ => self.indent_width(depth),
+        }
+    }
+
+    /// `depth` 段分のインデントの幅
+    fn indent_width(&self, depth: usize) -> usize {
+        pretty::line_width(&self.indent(depth), self.option.tab_width)
+    }
+
+    /// 行の `column` の位置から `output` を置いたときの、最後の行の末尾の列
+    fn column_after(&self, output: &str, column: usize) -> usize {
+        match output.rsplit_once('\n') {
+            Some((_, last)) => pretty::line_width(last, self.option.tab_width),
+            None => column + pretty::line_width(output, self.option.tab_width),
+        }
+    }
+
+    /// 子要素にコメントが付いているか (header と一緒に移動するコメントは除く)
+    fn has_comments(&self, cst: &Cst) -> bool {
+        attached_comments(&self.comments, cst)
+            .any(|comment| !self.header_comments.contains(&comment.span.start))
+    }
+
+    /// 直前の独立した行に付いたコメントを含めた cst の開始位置
+    fn start_with_comments(&self, cst: &Cst) -> usize {
+        let end = self
+            .comments
+            .partition_point(|comment| comment.span.start < cst.span.start);
+        self.comments
+            .range(..end)
+            .rev()
+            .take_while(|comment| {
+                comment.kind == CommentKind::Leading && comment.node == Some(cst.span)
+            })
+            .last()
+            .map_or(cst.span.start, |comment| comment.span.start)
+    }
+
+    /// コメントが付いている子要素であれば、format した文字列を覚えておく
+    fn record_output(&self, cst: &Cst, output: String) -> String {
+        if let Some(recorded) = self
+            .outputs
+            .borrow_mut()
+            .get_mut(&(cst.span.start, cst.span.end))
+        {
+            recorded.clone_from(&output);
+        }
+        output
+    }
+
+    /// 子要素に付いたコメントを、cst を format した文字列 `output` に入れる
+    /// `inner` は子要素を結合しただけの文字列で、これに無いコメントが `output` にあれば
+    /// 元の文字列をそのまま出力したものとして、コメントは入れない
+    /// * `depth` - cst が置かれる位置のインデントの深さ
+    /// * `inner_depth` - cst の内側のインデントの深さ
+    fn place_comments<'c>(
+        &self,
+        text: &str,
+        cst: &'c Cst,
+        inner: &str,
+        output: String,
+        depth: usize,
+        inner_depth: usize,
+    ) -> String {
+        let mut output = output;
+        // 子要素と、その文字列が置かれた範囲
+        let mut nodes = {
+            let outputs = self.outputs.borrow();
+            let mut from = 0;
+            cst.inner
+                .iter()
+                .map(|node| {
+                    let range = outputs
+                        .get(&(node.span.start, node.span.end))
+                        .and_then(|node| find_node_output(&output, node, from));
+                    if let Some(range) = &range {
+                        from = range.end;
+                    }
+                    (node, range)
+                })
+                .collect::<Vec<_>>()
+        };
+        for comment in attached_comments(&self.comments, cst) {
+            if self.header_comments.contains(&comment.span.start) {
+                // headers を並べ替えるときに header と一緒に出力する
+                continue;
+            }
+            let source = text[comment.span.start..comment.span.end].trim_end();
+            if output.contains(source) && !inner.contains(source) {
+                continue;
+            }
+            let comment_text = to_comment_string(source.to_string());
+            // `off` と `on` の間のコメントは折り返さない
+            if self
+                .verbatim
+                .iter()
+                .any(|range| range.contains(&comment.span.start))
+            {
+                self.raw.borrow_mut().push(comment_text.clone());
+            }
+            let located =
+                |(node, range): &(&'c Cst, Option<Range<usize>>)| Some((*node, range.clone()?));
+            let node = match nodes
+                .iter()
+                .find(|(node, _)| Some(node.span) == comment.node)
+                .and_then(located)
+            {
+                Some(node) => Some((comment.kind, node)),
+                // 付いている要素の文字列が見つからなければ、前後の要素に付け直す
+                None => match nodes
+                    .iter()
+                    .rev()
+                    .filter(|(node, _)| node.span.end <= comment.span.start)
+                    .find_map(located)
+                {
+                    Some(before) => Some((CommentKind::Dangling, before)),
+                    None => nodes
+                        .iter()
+                        .filter(|(node, _)| comment.span.end <= node.span.start)
+                        .find_map(located)
+                        .map(|after| (CommentKind::Leading, after)),
+                },
+            };
+            let (range, replace) = match node {
+                Some((CommentKind::Leading, (node, range))) => {
+                    let start = line_start(&output, range.start);
+                    let before = &output[start..range.start];
+                    let indent = self.line_indent(&output, range.start, depth);
+                    if !before.trim().is_empty() {
+                        // 行の途中にある要素の前では改行する
+                        let indent = if is_inline_context(cst.rule) {
+                            indent
+                        } else {
+                            indent + &self.indent(1)
+                        };
+                        let code = start + before.trim_end().len();
+                        (
+                            code..range.start,
+                            format!("\n{indent}{comment_text}\n{indent}"),
+                        )
+                    } else if node.rule == Rule::headers && blank_line_after(text, comment) {
+                        // ファイル先頭のコメントと header の間の空行は残す
+                        (
+                            range.start..range.start,
+                            format!("{comment_text}\n\n{indent}"),
+                        )
+                    } else {
+                        (
+                            range.start..range.start,
+                            format!("{comment_text}\n{indent}"),
+                        )
+                    }
+                }
+                Some((CommentKind::Trailing, (_, range))) => {
+                    self.trailing.borrow_mut().push(comment_text.clone());
+                    let end = line_end(&output, range.end);
+                    let rest = &output[range.end..end];
+                    if is_closing(rest) && end < output.len() {
+                        // 行末に置く
+                        let code = range.end + rest.trim_end().len();
+                        (code..end, format!(" {comment_text}"))
+                    } else {
+                        // 区切りの後に置き、続きは次の行に送る
+                        let (at, next) = after_separator(&output, range.end);
+                        let indent = if next == output.len() {
+                            self.indent(depth)
+                        } else {
+                            self.line_indent(&output, range.end, depth)
+                        };
+                        (at..next, format!(" {comment_text}\n{indent}"))
+                    }
+                }
+                Some((CommentKind::Dangling, (_, range))) => {
+                    let end = line_end(&output, range.end);
+                    let rest = &output[range.end..end];
+                    let indent = self.line_indent(&output, range.end, depth);
+                    if is_closing(rest) && end < output.len() {
+                        (end..end, format!("\n{indent}{comment_text}"))
+                    } else {
+                        let (at, next) = after_separator(&output, range.end);
+                        let after = if next == output.len() {
+                            self.indent(depth)
+                        } else {
+                            indent.clone()
+                        };
+                        (at..next, format!("\n{indent}{comment_text}\n{after}"))
+                    }
+                }
+                None if output.trim().is_empty() => (
+                    0..output.len(),
+                    format!("{comment_text}\n{}", self.indent(depth)),
+                ),
+                None => {
+                    // 子要素が無ければ閉じ括弧の前に置く
+                    let close = if output.ends_with("|)") {
+                        output.len() - 2
+                    } else if output.ends_with([')', ']', '}', '>']) {
+                        output.len() - 1
+                    } else {
+                        output.len()
+                    };
+                    let replace = format!(
+                        "\n{}{comment_text}\n{}",
+                        self.indent(inner_depth),
+                        self.indent(depth)
+                    );
+                    (close..close, replace)
+                }
+            };
+            // 後ろの子要素の位置をずらす
+            for (_, node) in nodes.iter_mut() {
+                if let Some(node) = node.as_mut().filter(|node| node.start >= range.end) {
+                    *node = node.start + replace.len() - range.len()
+                        ..node.end + replace.len() - range.len();
+                }
+            }
+            output.replace_range(range, &replace);
+        }
+        output
+    }
+
+    /// `output` の `position` がある行の先頭の空白
+    /// cst の最初の行は `depth` 段のインデントの位置から始まるものとする
+    fn line_indent(&self, output: &str, position: usize, depth: usize) -> String {
+        let start = line_start(output, position);
+        let line = &output[start..line_end(output, start)];
+        let indent = &line[..line.len() - line.trim_start().len()];
+        if start == 0 {
+            self.indent(depth) + indent
+        } else {
+            indent.to_string()
+        }
+    }
+
+    /// そのまま出力する文字列を返す
+    /// 複数行にわたるものは、中の `%` の行をコメントとして折り返さないよう覚えておく
+    fn keep_raw(&self, text: &str) -> String {
+        if text.contains('\n') {
+            self.raw.borrow_mut().push(text.to_string());
+        }
+        text.to_string()
+    }
+
+    /// format せずに元の文字列をそのまま出力する要素かどうか
+    /// `off` と `on` の間に収まる要素と、`skip` の直後から始まる最も外側の要素が該当する
+    fn is_verbatim(&self, text: &str, cst: &Cst) -> bool {
+        if cst.rule == Rule::misc {
+            return false;
+        }
+        let span = cst.span.start..cst.span.end;
+        if text[span.clone()].trim().is_empty() {
+            return false;
+        }
+        let in_verbatim = self
+            .verbatim
+            .iter()
+            .any(|range| range.start <= span.start && span.end <= range.end);
+        let after_skip = self
+            .skips
+            .iter()
+            .any(|&skip| skip <= span.start && text[skip..span.start].trim().is_empty());
+        in_verbatim || after_skip
+    }
+
     /// cst を文字列にするための関数
     fn to_string_cst(&self, text: &str, cst: &Cst, depth: usize) -> String {
+        if self.is_verbatim(text, cst) {
+            let output = self.keep_raw(&text[cst.span.start..cst.span.end]);
+            return self.record_output(cst, output);
+        }
         // インデントを制御するための変数
-        let new_depth = match cst.rule {
-            Rule::block_text | Rule::cmd_text_arg | Rule::record | Rule::type_record => depth + 1,
-            // Rule::horizontal_list | Rule::list => depth + 1,
-            Rule::list => depth + 1,
-            Rule::type_block_cmd | Rule::type_inline_cmd | Rule::math_cmd => depth + 1,
-            Rule::match_expr | Rule::let_rec_matcharm => depth + 1,
-            Rule::let_rec_inner => depth + 1,
-            Rule::sig_stmt | Rule::struct_stmt => depth + 1,
-            _ => depth,
-        };
-        let start_indent = "\n".to_string() + &indent_space(self.option.indent_space, new_depth);
-        let end_indent = "\n".to_string() + &indent_space(self.option.indent_space, depth);
+        let new_depth = next_depth(cst.rule, depth);
+        let start_indent = "\n".to_string() + &self.indent(new_depth);
+        let end_indent = "\n".to_string() + &self.indent(depth);
 
         let output = self.to_string_cst_inner(text, cst, new_depth);
+        // 子要素を結合しただけの文字列 (コメントを入れるときに使う)
+        let inner = self.has_comments(cst).then(|| output.clone());
         let self_text = text.get(cst.span.start..cst.span.end).unwrap().to_string();
 
         use satysfi_parser::Rule;
         // 中身をそのまま返すものは output をそのまま返す
         // self_text は元の文字列をそのまま返したいときに使用
-        match cst.rule {
-            Rule::comments => to_comment_string(self_text) + &end_indent,
+        let output = match cst.rule {
+            // コメントは付いている要素の親で place_comments により出力する
+            Rule::comments => String::new(),
             // header
             // stage の次は必ず改行する
             Rule::stage => "@stage: ".to_string() + &self_text + "\n\n",
@@ -1076,7 +1583,16 @@ impl<'a> Formatter<'a> {
             Rule::type_variant => output,
             Rule::module_stmt => format!("{start_indent}{} {}", RESERVED_WORD.module, output),
             Rule::open_stmt => format!("{} {output}", RESERVED_WORD.open),
-            Rule::arg => self_text,
+            // pattern だけの引数は pattern として format する
+            Rule::arg => match cst.inner.as_slice() {
+                [inner]
+                    if inner.rule == Rule::pattern
+                        && text[inner.span.start..inner.span.end] == self_text =>
+                {
+                    output
+                }
+                _ => self_text,
+            },
 
             // struct
             Rule::sig_stmt => format!(
@@ -1150,7 +1666,7 @@ impl<'a> Formatter<'a> {
                 let trimed_self_text: String = self_text.split(char::is_whitespace).collect();
                 if output.is_empty() {
                     "[]".to_string()
-                } else if trimed_self_text.len() < 15 {
+                } else if pretty::text_width(&trimed_self_text) < 15 && !self.has_comments(cst) {
                     // list の文字の長さが十分に短い easy tableの [l;c;r;] など
                     let inner = output
                         .split('\n')
@@ -1165,8 +1681,8 @@ impl<'a> Formatter<'a> {
                 }
             }
             Rule::record | Rule::type_record => {
-                if cst.inner.len() > 1 {
-                    // 2 つ以上のときは改行
+                if cst.inner.len() > 1 || self.has_comments(cst) {
+                    // 2 つ以上のときやコメントを含むときは改行
                     format!("(|{start_indent}{output}{end_indent}|)")
                 } else {
                     // 1つだけの時は、改行しない
@@ -1178,10 +1694,7 @@ impl<'a> Formatter<'a> {
             Rule::bin_operator => {
                 if self_text == "|>" {
                     // 1つ深くする
-                    format!(
-                        "{start_indent}{}{self_text}",
-                        indent_space(self.option.indent_space, 1)
-                    )
+                    format!("{start_indent}{}{self_text}", self.indent(1))
                 } else {
                     self_text
                 }
@@ -1218,13 +1731,21 @@ impl<'a> Formatter<'a> {
                 let include_comment = output.starts_with('%');
                 let include_kaigyou =
                     output.find('\n') != None || start_arg == '<' || include_comment;
+                // 開き括弧の位置から 1 行に並べたときの幅 (全角文字は 2 文字分の幅として数える)
+                let width = self.start_column(cst, depth)
+                    + pretty::line_width(
+                        &format!("{start_arg} {} {end_arg}", output.trim()),
+                        self.option.tab_width,
+                    );
                 match output.trim().len() {
                     0 => format!("{start_arg}{end_arg}"),
                     // easytable
                     _ if output.starts_with(char::is_whitespace) => {
                         format!("{start_arg}\n{output}{end_arg}")
                     }
-                    num if include_kaigyou || num > self.option.row_length => {
+                    _ if include_kaigyou || width > self.option.row_length => {
+                        // コメントで終わるときの改行は end_indent で置き換える
+                        let output = output.trim_end();
                         format!("{start_arg}{start_indent}{output}{end_indent}{end_arg}")
                     }
                     _ => format!("{start_arg} {output} {end_arg}"),
@@ -1255,10 +1776,25 @@ impl<'a> Formatter<'a> {
             // pattern
             Rule::pat_as => output,
             Rule::pat_cons => output,
-            Rule::pattern => self_text, // TODO どのパターンでも中身をそのまま出力
+            Rule::pattern => {
+                if cst.inner.is_empty() {
+                    // `_`
+                    self_text.trim().to_string()
+                } else if self_text.starts_with('(')
+                    && cst
+                        .inner
+                        .iter()
+                        .all(|inner| !matches!(inner.rule, Rule::pat_tuple | Rule::const_unit))
+                {
+                    // 括弧で囲まれた pattern
+                    format!("({})", output.trim())
+                } else {
+                    output
+                }
+            }
             Rule::pat_variant => output,
             Rule::pat_list => format!("[{output}]"),
-            Rule::pat_tuple => output, // TODO
+            Rule::pat_tuple => format!("({output})"),
 
             // expr
             Rule::expr => {
@@ -1295,7 +1831,7 @@ impl<'a> Formatter<'a> {
             // horizontal
             Rule::horizontal_single => output,
             Rule::horizontal_list => {
-                let sep = format!("\n{}", indent_space(self.option.indent_space, new_depth));
+                let sep = format!("\n{}", self.indent(new_depth));
                 let output = self_text
                     .split('\n')
                     .into_iter()
@@ -1309,20 +1845,20 @@ impl<'a> Formatter<'a> {
                     .collect::<Vec<String>>()
                     .join(&sep);
                 // output
-                format!(
-                    "{}{output}",
-                    indent_space(self.option.indent_space, new_depth)
-                )
+                format!("{}{output}", self.indent(new_depth))
             }
             Rule::horizontal_bullet_list => output, // TODO
             Rule::horizontal_bullet => output,      // TODO
             Rule::horizontal_bullet_star => {
-                " ".repeat(self.option.indent_space / 2)
-                    .repeat(self_text.len() - 1)
-                    + &self_text
+                // 入れ子の深さの分だけ字下げする
+                let unit = match self.option.indent_style {
+                    IndentStyle::Space => " ".repeat(self.option.indent_space / 2),
+                    IndentStyle::Tab => "\t".to_string(),
+                };
+                unit.repeat(self_text.len() - 1) + &self_text
             }
             Rule::regular_text => {
-                let sep = format!("\n{}", indent_space(self.option.indent_space, depth));
+                let sep = format!("\n{}", self.indent(depth));
                 let output = self_text
                     .split('\n')
                     .into_iter()
@@ -1370,7 +1906,7 @@ impl<'a> Formatter<'a> {
             Rule::const_int => self_text,
             Rule::const_float => self_text,
             Rule::const_length => self_text,
-            Rule::const_string => self_text,
+            Rule::const_string => self.keep_raw(&self_text),
 
             // math
             Rule::math_single => output, // TODO
@@ -1404,19 +1940,115 @@ impl<'a> Formatter<'a> {
             Rule::program_saty => output.trim_start().to_string(),
             Rule::program_satyh => output.trim_start().to_string(),
             Rule::preamble => output.trim_start().to_string(),
-            // TODO
             // dummy
-            Rule::dummy_header => panic!("found dummy header"),
-            Rule::dummy_sig_stmt => panic!("found dummy sig_stmt"),
-            Rule::dummy_stmt => panic!("found dummy stmt"),
-            Rule::dummy_inline_cmd_incomplete => self_text, // panic!("found dummy inline_cmd"), mdja.satyh のparseで到達する
-            Rule::dummy_block_cmd_incomplete => panic!("found dummy block_cmd"),
-            Rule::dummy_modvar_incomplete => panic!("found dummy modvar"),
+            // 書きかけの構文は try_format でエラーとして報告するので、ここでは元の文字列をそのまま出力する
+            Rule::dummy_header => self_text,
+            Rule::dummy_sig_stmt => self_text,
+            Rule::dummy_stmt => self_text,
+            Rule::dummy_inline_cmd_incomplete => self_text, // mdja.satyh のparseで到達する
+            Rule::dummy_block_cmd_incomplete => self_text,
+            Rule::dummy_modvar_incomplete => self_text,
             // _ => unreachable!(),
+        };
+        let output = match inner {
+            Some(inner) => self.place_comments(text, cst, &inner, output, depth, new_depth),
+            None => output,
+        };
+        self.record_output(cst, output)
+    }
+}
+/// `rule` の要素の内側のインデントの深さ
+pub fn next_depth(rule: Rule, depth: usize) -> usize {
+    match rule {
+        Rule::block_text | Rule::cmd_text_arg | Rule::record | Rule::type_record => depth + 1,
+        // Rule::horizontal_list | Rule::list => depth + 1,
+        Rule::list => depth + 1,
+        Rule::type_block_cmd | Rule::type_inline_cmd | Rule::math_cmd => depth + 1,
+        Rule::match_expr | Rule::let_rec_matcharm => depth + 1,
+        Rule::let_rec_inner => depth + 1,
+        Rule::sig_stmt | Rule::struct_stmt => depth + 1,
+        _ => depth,
+    }
+}
+
+/// コメントが付いている要素の子要素の位置を集める
+fn comment_nodes(
+    cst: &Cst,
+    comments: &VecDeque<Comment>,
+    nodes: &mut HashMap<(usize, usize), String>,
+) {
+    if attached_comments(comments, cst).next().is_some() {
+        for inner in cst.inner.iter() {
+            nodes.insert((inner.span.start, inner.span.end), String::new());
         }
     }
+    for inner in cst.inner.iter() {
+        comment_nodes(inner, comments, nodes);
+    }
+}
+
+/// `position` がある行の先頭の位置
+fn line_start(output: &str, position: usize) -> usize {
+    output[..position].rfind('\n').map_or(0, |index| index + 1)
+}
+
+/// `position` がある行の末尾 (改行の前) の位置
+fn line_end(output: &str, position: usize) -> usize {
+    output[position..]
+        .find('\n')
+        .map_or(output.len(), |index| position + index)
+}
+
+/// 行の残りが閉じ括弧や区切りだけか
+fn is_closing(rest: &str) -> bool {
+    rest.chars()
+        .all(|c| c.is_whitespace() || matches!(c, ';' | ',' | ')' | ']' | '}' | '>' | '|'))
+}
+
+/// `position` の直後の `;` や `,` の後の位置と、それに続く同じ行の空白の後の位置
+fn after_separator(output: &str, position: usize) -> (usize, usize) {
+    let rest = &output[position..line_end(output, position)];
+    let at = position + rest.len() - rest.trim_start_matches([';', ',']).len();
+    let rest = &output[at..line_end(output, at)];
+    (at, at + rest.len() - rest.trim_start().len())
+}
+
+/// コメントの後に空行があるか
+fn blank_line_after(text: &str, comment: &Comment) -> bool {
+    // コメントの span は次の行の先頭までなので、続く空白に改行があれば空行
+    let rest = &text[comment.span.end..];
+    rest[..rest.len() - rest.trim_start().len()].contains('\n')
 }
-#[inline]
-fn indent_space(unit: usize, depth: usize) -> String {
-    " ".repeat(unit * depth)
+
+/// header と一緒に移動するコメントの開始位置を集める
+/// header の間にあるコメントと、最初の header の直上に空行を挟まずに続くコメントが該当する
+fn get_header_comments(text: &str, cst: &Cst, comments: &VecDeque<Comment>) -> Vec<usize> {
+    let headers = match cst.inner.iter().find(|inner| inner.rule == Rule::headers) {
+        Some(headers) => headers,
+        None => return vec![],
+    };
+    let (first, last) = match (headers.inner.first(), headers.inner.last()) {
+        (Some(first), Some(last)) => (first.span.start, last.span.start),
+        _ => return vec![],
+    };
+    let mut starts = comments
+        .iter()
+        .map(|comment| comment.span.start)
+        .filter(|&start| first < start && start < last)
+        .collect::<Vec<_>>();
+    // ライセンス表記など、空行で区切られたファイル先頭のコメントはそのまま残す
+    let mut next = first;
+    for comment in comments
+        .iter()
+        .rev()
+        .filter(|comment| comment.span.start < first)
+    {
+        if text[comment.span.start..next].matches('\n').count() != 1 {
+            break;
+        }
+        starts.push(comment.span.start);
+        next = comment.span.start;
+    }
+    starts.sort_unstable();
+    starts
 }
@@ -1,19 +1,45 @@
 mod comment;
+mod error;
 mod formatter;
+mod pretty;
+mod range;
 mod reserved_words;
 #[cfg(test)]
 mod tests;
+mod verify;
 mod visualize;
 
-use comment::*;
+pub use error::FormatError;
 use formatter::Formatter;
-use satysfi_parser::{grammar, CstText};
+pub use range::{apply_edits, format_range, lines_to_range, TextEdit};
+use satysfi_parser::{grammar, Cst, CstText, Rule};
+use std::path::Path;
+use std::str::FromStr;
+pub use verify::{check_equivalence, check_idempotency};
 pub use visualize::*;
 
+#[derive(Clone, Debug)]
 pub struct OptionData {
     pub row_length: usize,
     pub indent_space: usize,
     pub command_args_space: bool,
+    pub source_kind: SourceKind,
+    /// 連続する行の行末のコメントの位置を揃える
+    pub align_trailing_comments: bool,
+    /// 独立した行のコメントを `row_length` に収まるよう折り返す
+    pub wrap_comments: bool,
+    /// インデントに使う文字
+    pub indent_style: IndentStyle,
+    /// 1 行の長さを測るときのタブ 1 つの幅
+    pub tab_width: usize,
+    /// 出力の改行文字
+    pub end_of_line: EndOfLine,
+    /// 出力の末尾を改行で終える
+    pub insert_final_newline: bool,
+    /// `@require`, `@import` を重複を除いて名前順に並べる
+    pub sort_headers: bool,
+    /// 行内のテキストを row_length に収まるよう詰めて折り返す
+    pub fill_text: bool,
 }
 
 impl Default for OptionData {
@@ -22,31 +48,143 @@ impl Default for OptionData {
             row_length: 80,
             indent_space: 4,
             command_args_space: true,
+            source_kind: SourceKind::Auto,
+            align_trailing_comments: false,
+            wrap_comments: false,
+            indent_style: IndentStyle::Space,
+            tab_width: 4,
+            end_of_line: EndOfLine::Auto,
+            insert_final_newline: true,
+            sort_headers: false,
+            fill_text: false,
         }
     }
 }
 
+/// インデントに使う文字
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndentStyle {
+    /// `indent_space` 個の空白
+    Space,
+    /// 1 段につきタブ 1 つ
+    Tab,
+}
+
+impl FromStr for IndentStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "space" => Ok(IndentStyle::Space),
+            "tab" => Ok(IndentStyle::Tab),
+            _ => Err(format!(
+                "unknown indent style `{s}` (expected `space` or `tab`)"
+            )),
+        }
+    }
+}
+
+/// 入力のソースの種類 (parse に使う文法を決める)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceKind {
+    /// 文書ファイルとパッケージファイルのどちらとしても parse を試みる
+    Auto,
+    /// 文書ファイル (.saty)
+    Saty,
+    /// パッケージファイル (.satyh, .satyg)
+    Satyh,
+}
+
+impl SourceKind {
+    /// 拡張子からソースの種類を判定する
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("saty") => SourceKind::Saty,
+            Some("satyh") | Some("satyg") => SourceKind::Satyh,
+            _ => SourceKind::Auto,
+        }
+    }
+}
+
+/// 改行文字
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EndOfLine {
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    Crlf,
+    /// 入力で多く使われている方に揃える
+    Auto,
+}
+
+impl EndOfLine {
+    /// `Auto` の場合は入力で多く使われている改行文字を返す
+    pub fn resolve(self, input: &str) -> EndOfLine {
+        match self {
+            EndOfLine::Auto => {
+                let crlf = input.matches("\r\n").count();
+                let lf = input.matches('\n').count() - crlf;
+                if crlf > lf {
+                    EndOfLine::Crlf
+                } else {
+                    EndOfLine::Lf
+                }
+            }
+            eol => eol,
+        }
+    }
+
+    /// LF で書かれた `text` の改行をこの改行文字にする
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            EndOfLine::Crlf => text.replace('\n', "\r\n"),
+            EndOfLine::Lf | EndOfLine::Auto => text.to_string(),
+        }
+    }
+}
+
+impl FromStr for EndOfLine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lf" => Ok(EndOfLine::Lf),
+            "crlf" => Ok(EndOfLine::Crlf),
+            "auto" => Ok(EndOfLine::Auto),
+            _ => Err(format!(
+                "unknown end of line `{s}` (expected `lf`, `crlf` or `auto`)"
+            )),
+        }
+    }
+}
+
+/// 改行を LF に揃える
+/// format は LF の入力に対して行い、出力するときに `EndOfLine` に合わせる
+fn normalize_newlines(input: &str) -> String {
+    input.replace("\r\n", "\n")
+}
+
 /// satysfi の文字列を渡すと format したものを返す
+/// parse に失敗した場合はエラーを表示し、入力をそのまま返す
 /// * `input` - satysfi のコード
 /// * `output` - format された文字列
 pub fn format(input: &str, option: OptionData) -> String {
-    /*
-    CstText {
-        text: string,
-        lines: Vec<usize>, // start
-        cst: Cst,
+    match try_format(input, option) {
+        Ok(output) => output,
+        Err(err) => {
+            eprintln!("disable to format\n{err}");
+            input.to_string()
+        }
     }
-    */
-    let csttext = CstText::parse(input, grammar::program);
-    if csttext.is_err() {
-        let err = csttext.unwrap_err();
-        let line = err.0.line;
-        let col = err.0.column;
-        eprintln!("disable to format\n[parse error] line: {}, column: {}", line, col);
-        return input.to_string();
-    }
-    let csttext = csttext.unwrap();
-    let csttext = csttext_insert_comments(csttext);
+}
+
+/// satysfi の文字列を渡すと format したものを返す
+/// * `input` - satysfi のコード
+/// * `output` - format された文字列、parse に失敗した場合は `FormatError`
+pub fn try_format(input: &str, option: OptionData) -> Result<String, FormatError> {
+    let end_of_line = option.end_of_line.resolve(input);
+    let input = &normalize_newlines(input);
+    let csttext = parse(input, option.source_kind)?;
     let formatter = Formatter::new(&csttext, option);
 
     #[cfg(debug_assertions)]
@@ -55,5 +193,70 @@ pub fn format(input: &str, option: OptionData) -> String {
     let depth = 0;
     let output = formatter.format(input, &csttext.cst, depth);
 
-    output
+    Ok(end_of_line.apply(&output))
+}
+
+/// `input` を parse できるか確認する
+/// format はしないので、入力中の文書の診断に使う
+pub fn check_syntax(input: &str, source_kind: SourceKind) -> Result<(), FormatError> {
+    parse(&normalize_newlines(input), source_kind).map(|_| ())
+}
+
+/// `input` を parse する
+/// 書きかけの構文が含まれている場合もエラーにする
+fn parse(input: &str, source_kind: SourceKind) -> Result<CstText, FormatError> {
+    /*
+    CstText {
+        text: string,
+        lines: Vec<usize>, // start
+        cst: Cst,
+    }
+    */
+    let csttext = match source_kind {
+        SourceKind::Auto => CstText::parse(input, grammar::program),
+        SourceKind::Saty => CstText::parse(input, grammar::program_saty),
+        SourceKind::Satyh => CstText::parse(input, grammar::program_satyh),
+    };
+    let csttext = csttext.map_err(|(pos, expected)| FormatError::Parse {
+        line: pos.line,
+        column: pos.column,
+        expected: expected.iter().map(|rule| rule.to_string()).collect(),
+    })?;
+    if let Some(cst) = find_incomplete(&csttext.cst) {
+        let (line, column) = line_col(&csttext, cst.span.start);
+        return Err(FormatError::Incomplete {
+            rule: format!("{:?}", cst.rule),
+            start: cst.span.start,
+            end: cst.span.end,
+            line,
+            column,
+        });
+    }
+    Ok(csttext)
+}
+
+/// 書きかけの構文 (dummy 規則) を探す
+/// dummy_inline_cmd_incomplete は既存のパッケージにも現れるため、そのまま出力する
+fn find_incomplete(cst: &Cst) -> Option<&Cst> {
+    match cst.rule {
+        Rule::dummy_header
+        | Rule::dummy_sig_stmt
+        | Rule::dummy_stmt
+        | Rule::dummy_block_cmd_incomplete
+        | Rule::dummy_modvar_incomplete => Some(cst),
+        _ => cst.inner.iter().find_map(find_incomplete),
+    }
+}
+
+/// byte 位置から 1 始まりの行と列を求める
+fn line_col(csttext: &CstText, pos: usize) -> (usize, usize) {
+    let line = csttext
+        .lines
+        .iter()
+        .filter(|&&start| start <= pos)
+        .count()
+        .max(1);
+    let start = csttext.lines.get(line - 1).copied().unwrap_or(0);
+    let column = csttext.text[start..pos].chars().count() + 1;
+    (line, column)
 }
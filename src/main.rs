@@ -1,40 +1,318 @@
-use clap::Parser;
-use satysfi_formatter::{format, OptionData};
-use std::{fs, path::PathBuf};
+mod cli;
+
+use clap::{ArgEnum, Parser, Subcommand};
+use cli::{
+    config::{resolve_option, Config},
+    diff::unified_diff,
+    files::collect_files,
+};
+use satysfi_formatter::{
+    apply_edits, check_equivalence, check_idempotency, format_range, lines_to_range, try_format,
+    EndOfLine, IndentStyle, OptionData,
+};
+use std::{
+    fs,
+    io::{self, IsTerminal, Read},
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+    process,
+};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
-    /// input file
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// input files or directories (directories are searched recursively).
+    /// read from stdin if omitted or `-`
     #[clap(parse(from_os_str), value_name = "FILE")]
-    file: PathBuf,
+    files: Vec<PathBuf>,
+    /// path used to choose the grammar and configuration when reading from stdin
+    #[clap(long, parse(from_os_str), value_name = "PATH")]
+    stdin_filepath: Option<PathBuf>,
     /// write to input file
     #[clap(short, long)]
     write: bool,
     /// output file
     #[clap(short, long)]
     output: Option<PathBuf>,
-    /// indent size
-    #[clap(short, long, default_value_t = 4)]
-    indent_space: usize,
+    /// check whether the input file is formatted (exit with 1 if not)
+    #[clap(long, conflicts_with_all = &["write", "output"])]
+    check: bool,
+    /// print the diff between the input file and the formatted output
+    #[clap(long, conflicts_with_all = &["write", "output"])]
+    diff: bool,
+    /// check that the formatted output parses to the same syntax tree as the input
+    /// and refuse to write it otherwise (enabled by default with --check)
+    #[clap(long)]
+    verify: bool,
+    /// skip the verification enabled by --check
+    #[clap(long, conflicts_with = "verify")]
+    no_verify: bool,
+    /// check that formatting the output again does not change it
+    #[clap(long)]
+    check_idempotency: bool,
+    /// colorize the diff output
+    #[clap(long, arg_enum, value_name = "WHEN", default_value = "auto")]
+    color: Color,
+    /// indent size [default: 4]
+    #[clap(short, long)]
+    indent_space: Option<usize>,
+    /// indent with `space` or `tab` [default: space]
+    #[clap(long, value_name = "STYLE")]
+    indent_style: Option<IndentStyle>,
+    /// width of a tab when measuring the length of a line [default: 4]
+    #[clap(long)]
+    tab_width: Option<usize>,
+    /// line ending of the output: `lf`, `crlf` or `auto` (same as the input) [default: auto]
+    #[clap(long, value_name = "EOL")]
+    end_of_line: Option<EndOfLine>,
+    /// max length of a line [default: 80]
+    #[clap(long)]
+    row_length: Option<usize>,
     /// Add space before arguments in command
     #[clap(long)]
     cspace: bool,
+    /// align trailing comments on consecutive lines to the same column
+    #[clap(long)]
+    align_comments: bool,
+    /// wrap comments on their own lines to fit in the max length of a line
+    #[clap(long)]
+    wrap_comments: bool,
+    /// sort `@require` and `@import` by name and remove duplicates
+    #[clap(long)]
+    sort_headers: bool,
+    /// fill inline text to fit in the max length of a line
+    #[clap(long)]
+    fill_text: bool,
+    /// format only the given byte range (e.g. `120:480`)
+    #[clap(long, value_name = "START:END", parse(try_from_str = parse_byte_range))]
+    range: Option<Range<usize>>,
+    /// format only the given lines (e.g. `10-20`, 1-based and inclusive)
+    #[clap(long, value_name = "FIRST-LAST", parse(try_from_str = parse_line_range), conflicts_with = "range")]
+    lines: Option<RangeInclusive<usize>>,
+}
+
+fn parse_byte_range(arg: &str) -> Result<Range<usize>, String> {
+    let (start, end) = arg
+        .split_once(':')
+        .ok_or_else(|| format!("expected START:END, found `{arg}`"))?;
+    let start = start.parse::<usize>().map_err(|err| err.to_string())?;
+    let end = end.parse::<usize>().map_err(|err| err.to_string())?;
+    if start > end {
+        return Err(format!("invalid range `{arg}`"));
+    }
+    Ok(start..end)
+}
+
+fn parse_line_range(arg: &str) -> Result<RangeInclusive<usize>, String> {
+    let (first, last) = arg
+        .split_once('-')
+        .ok_or_else(|| format!("expected FIRST-LAST, found `{arg}`"))?;
+    let first = first.parse::<usize>().map_err(|err| err.to_string())?;
+    let last = last.parse::<usize>().map_err(|err| err.to_string())?;
+    if first == 0 || first > last {
+        return Err(format!("invalid line range `{arg}`"));
+    }
+    Ok(first..=last)
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Start a language server speaking LSP over stdio
+    Lsp,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    fn enabled(self) -> bool {
+        match self {
+            Color::Auto => io::stdout().is_terminal(),
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+/// format する入力の取得元
+enum Source<'a> {
+    Stdin,
+    File(&'a Path),
+}
+
+impl Cli {
+    /// format の設定を決める
+    /// 優先順位は CLI の引数 > 設定ファイル > .editorconfig > デフォルト値
+    fn option(&self, path: Option<&Path>) -> io::Result<OptionData> {
+        let overrides = Config {
+            row_length: self.row_length,
+            indent_space: self.indent_space,
+            command_args_space: self.cspace.then_some(true),
+            align_trailing_comments: self.align_comments.then_some(true),
+            wrap_comments: self.wrap_comments.then_some(true),
+            indent_style: self.indent_style,
+            tab_width: self.tab_width,
+            end_of_line: self.end_of_line,
+            sort_headers: self.sort_headers.then_some(true),
+            fill_text: self.fill_text.then_some(true),
+            ..Default::default()
+        };
+        resolve_option(path, &overrides)
+    }
+
+    /// format の結果を検証するかどうか
+    fn verify(&self) -> bool {
+        self.verify || (self.check && !self.no_verify)
+    }
+
+    /// 標準入力から読み込むかどうか
+    fn is_stdin(&self) -> bool {
+        self.files.is_empty() || (self.files.len() == 1 && self.files[0] == Path::new("-"))
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
-    let code = fs::read_to_string(&cli.file).expect("Failed to read file");
-    let option = OptionData {
-        indent_space: cli.indent_space,
-        command_args_space: cli.cspace,
-        ..Default::default()
+    if let Some(Command::Lsp) = cli.command {
+        if let Err(err) = cli::lsp::run() {
+            eprintln!("{err}");
+            process::exit(2);
+        }
+        return;
+    }
+    if cli.is_stdin() {
+        if cli.write {
+            eprintln!("--write cannot be used when reading from stdin");
+            process::exit(2);
+        }
+        match format_source(&cli, Source::Stdin) {
+            Ok(changed) => {
+                if cli.check && changed {
+                    process::exit(1);
+                }
+                return;
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                process::exit(2);
+            }
+        }
+    }
+
+    let files = collect_files(&cli.files).unwrap_or_else(|err| {
+        eprintln!("Failed to read directory: {err}");
+        process::exit(2);
+    });
+    if cli.output.is_some() && files.len() != 1 {
+        eprintln!("--output can be used only with a single input file");
+        process::exit(2);
+    }
+    if (cli.range.is_some() || cli.lines.is_some()) && files.len() != 1 {
+        eprintln!("--range and --lines can be used only with a single input file");
+        process::exit(2);
+    }
+
+    let mut changed = 0;
+    let mut failed = false;
+    for file in files.iter() {
+        match format_source(&cli, Source::File(file)) {
+            Ok(true) => changed += 1,
+            Ok(false) => {}
+            Err(err) => {
+                eprintln!("{err}");
+                failed = true;
+            }
+        }
+    }
+
+    // 複数ファイルを処理したときは集計を表示する
+    if files.len() > 1 {
+        if cli.check {
+            eprintln!("{changed} of {} files would be reformatted", files.len());
+        } else if cli.write {
+            eprintln!("{changed} of {} files reformatted", files.len());
+        }
+    }
+
+    if failed {
+        process::exit(2);
+    }
+    if cli.check && changed > 0 {
+        process::exit(1);
+    }
+}
+
+/// 1つの入力を format する
+/// format によって内容が変わる場合は `true` を返す
+/// 失敗した場合は表示するエラーメッセージを返す
+fn format_source(cli: &Cli, source: Source) -> Result<bool, String> {
+    let path = match source {
+        Source::Stdin => cli.stdin_filepath.as_deref(),
+        Source::File(file) => Some(file),
+    };
+    let name = match source {
+        Source::Stdin => path.map_or("<stdin>".to_string(), |path| path.display().to_string()),
+        Source::File(file) => file.display().to_string(),
     };
-    let output = format(&code, option);
+    let io_error = |err: io::Error| format!("{name}: {err}");
+
+    let code = match source {
+        Source::Stdin => {
+            let mut code = String::new();
+            io::stdin().read_to_string(&mut code).map_err(&io_error)?;
+            code
+        }
+        Source::File(file) => fs::read_to_string(file).map_err(&io_error)?,
+    };
+    let option = cli.option(path).map_err(&io_error)?;
+    let source_kind = option.source_kind;
+    if cli.check_idempotency {
+        check_idempotency(&code, option.clone()).map_err(|err| err.render(&name, &code))?;
+    }
+    let range = match (&cli.range, &cli.lines) {
+        (Some(range), _) => Some(range.clone()),
+        (None, Some(lines)) => Some(lines_to_range(&code, lines.clone())),
+        (None, None) => None,
+    };
+    let output = match range {
+        Some(range) => format_range(&code, range, option)
+            .map(|edits| apply_edits(&code, &edits))
+            .map_err(|err| err.render(&name, &code))?,
+        None => try_format(&code, option).map_err(|err| err.render(&name, &code))?,
+    };
+    let changed = output != code;
+    if changed && cli.verify() {
+        // 意味が変わっている場合は書き込まずにエラーにする
+        check_equivalence(&code, &output, source_kind).map_err(|err| err.render(&name, &code))?;
+    }
 
-    match (cli.output, cli.write) {
-        (Some(path), _) => fs::write(&path, output).expect("Failed to write file"),
-        (None, true) => fs::write(&cli.file, output).expect("Failed to write file"),
-        (None, false) => println!("{}", output),
+    if cli.diff {
+        print!(
+            "{}",
+            unified_diff(&name, &code, &output, cli.color.enabled())
+        );
+    } else if cli.check {
+        if changed {
+            // format すると変更されるファイルを報告する
+            println!("{name}");
+        }
+    } else if let Some(path) = &cli.output {
+        fs::write(path, output).map_err(&io_error)?;
+    } else if let (true, Source::File(file)) = (cli.write, &source) {
+        if changed {
+            fs::write(file, output).map_err(&io_error)?;
+        }
+    } else if let Source::Stdin = source {
+        // エディタから呼ばれることを想定して、余計な改行は出力しない
+        print!("{output}");
+    } else {
+        println!("{}", output);
     }
+    Ok(changed)
 }
@@ -0,0 +1,207 @@
+//! Wadler の "A prettier printer" に基づく文書の代数
+//! group の中身が行に収まる場合は 1 行で、収まらない場合は全ての line で改行して出力する
+//! formatter では関数適用、tuple、tuple と list の pattern、型の矢印の改行位置を決めるのに使う
+//! 子要素は先に文字列に format するので、1 行に収まった子要素だけを Text として並べる
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// 出力する文書
+#[derive(Clone, Debug)]
+pub enum Doc {
+    /// 文字列
+    /// 改行を含む場合は最初の行だけで幅を判定する
+    Text(String),
+    /// 1 行に収まる場合は中身の文字列、収まらない場合は改行
+    Line(&'static str),
+    Concat(Vec<Doc>),
+    /// 改行したときのインデントを深くする
+    Nest(String, Box<Doc>),
+    /// 1 行に収まるかどうかを判定する単位
+    Group(Box<Doc>),
+}
+
+impl Doc {
+    pub fn text<S: Into<String>>(text: S) -> Doc {
+        Doc::Text(text.into())
+    }
+
+    /// 1 行に収まる場合は空白 1 つ
+    pub fn line() -> Doc {
+        Doc::Line(" ")
+    }
+
+    /// 1 行に収まる場合は何も出力しない
+    pub fn softline() -> Doc {
+        Doc::Line("")
+    }
+
+    pub fn concat(docs: Vec<Doc>) -> Doc {
+        Doc::Concat(docs)
+    }
+
+    pub fn nest<S: Into<String>>(indent: S, doc: Doc) -> Doc {
+        Doc::Nest(indent.into(), Box::new(doc))
+    }
+
+    pub fn group(doc: Doc) -> Doc {
+        Doc::Group(Box::new(doc))
+    }
+
+    /// `docs` の間に `sep` を挟んで結合する
+    pub fn join(docs: Vec<Doc>, sep: Doc) -> Doc {
+        let mut output = vec![];
+        for (index, doc) in docs.into_iter().enumerate() {
+            if index > 0 {
+                output.push(sep.clone());
+            }
+            output.push(doc);
+        }
+        Doc::Concat(output)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// 文書を文字列にする
+/// * `width` - 1 行の最大幅
+/// * `indent` - 出力を置く位置のインデント (改行したときに先頭に付ける)
+/// * `column` - 1 行目が始まる列 (行の途中から置く場合は `indent` より右になる)
+/// * `tab_width` - タブ 1 つの幅
+pub fn render(doc: &Doc, width: usize, indent: &str, column: usize, tab_width: usize) -> String {
+    let text_width = |text: &str| line_width(text, tab_width);
+    let mut output = String::new();
+    let mut column = column;
+    let mut stack = vec![(indent.to_string(), Mode::Break, doc)];
+
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(text) => {
+                output += text;
+                column = match text.rfind('\n') {
+                    Some(index) => text_width(&text[index + 1..]),
+                    None => column + text_width(text),
+                };
+            }
+            Doc::Line(flat) if mode == Mode::Flat => {
+                output += flat;
+                column += text_width(flat);
+            }
+            Doc::Line(_) => {
+                output += "\n";
+                output += &indent;
+                column = text_width(&indent);
+            }
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((indent.clone(), mode, doc));
+                }
+            }
+            Doc::Nest(nest, doc) => stack.push((indent + nest, mode, doc)),
+            Doc::Group(doc) => {
+                let rest = width.saturating_sub(column);
+                let mode = if mode == Mode::Flat || fits(rest, doc, &stack, tab_width) {
+                    Mode::Flat
+                } else {
+                    Mode::Break
+                };
+                stack.push((indent, mode, doc));
+            }
+        }
+    }
+    output
+}
+
+/// `doc` を 1 行で出力したとき、続く文字列を含めて次の改行までが `rest` に収まるか判定する
+fn fits(rest: usize, doc: &Doc, stack: &[(String, Mode, &Doc)], tab_width: usize) -> bool {
+    let text_width = |text: &str| line_width(text, tab_width);
+    let mut rest = rest as isize;
+    let mut docs = vec![(Mode::Flat, doc)];
+    let mut stack = stack.iter().rev();
+
+    loop {
+        let (mode, doc) = match docs.pop() {
+            Some(next) => next,
+            None => match stack.next() {
+                Some((_, mode, doc)) => (*mode, *doc),
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(text) => match text.find('\n') {
+                Some(index) => return rest >= text_width(&text[..index]) as isize,
+                None => rest -= text_width(text) as isize,
+            },
+            Doc::Line(flat) if mode == Mode::Flat => rest -= text_width(flat) as isize,
+            Doc::Line(_) => return true,
+            Doc::Concat(inner) => {
+                for doc in inner.iter().rev() {
+                    docs.push((mode, doc));
+                }
+            }
+            Doc::Nest(_, doc) | Doc::Group(doc) => docs.push((mode, doc)),
+        }
+        if rest < 0 {
+            return false;
+        }
+    }
+}
+
+/// 単語を 1 行が `width` に収まるように詰めて並べる
+/// 単語は直前の単語との間で改行しない場合に挟む文字列と組にする
+/// 1 行目も `indent` の位置から始まるものとして数え、2 行目以降の行頭には `indent` を置く
+pub fn fill(words: &[(&str, String)], width: usize, indent: &str, tab_width: usize) -> String {
+    let indent_width = line_width(indent, tab_width);
+    let mut output = String::new();
+    let mut column = indent_width;
+    for (index, (sep, word)) in words.iter().enumerate() {
+        // 改行を含む単語は最初の行だけで幅を判定する
+        let word_width = line_width(word.split('\n').next().unwrap_or_default(), tab_width);
+        if index > 0 {
+            let sep_width = text_width(sep);
+            if column > indent_width && column + sep_width + word_width > width {
+                output += "\n";
+                output += indent;
+                column = indent_width;
+            } else {
+                output += sep;
+                column += sep_width;
+            }
+        }
+        output += word;
+        column = match word.rsplit_once('\n') {
+            Some((_, last)) => line_width(last, tab_width),
+            None => column + word_width,
+        };
+    }
+    output
+}
+
+/// 空白を挟まずに改行できる全角文字かどうか
+pub fn is_wide(c: char) -> bool {
+    c.width() == Some(2)
+}
+
+/// 2 つの全角文字の間で改行できるかどうか
+/// 閉じ括弧や句読点の前と、開き括弧の後では改行しない
+pub fn can_break_between(before: char, after: char) -> bool {
+    is_wide(before)
+        && is_wide(after)
+        && !"（「『【〔〈《［｛“‘".contains(before)
+        && !"）」』】〕〉》］｝”’、。，．・：；！？ー々ぁぃぅぇぉっゃゅょゎァィゥェォッャュョヮヵヶ"
+            .contains(after)
+}
+
+/// 出力したときの文字列の幅
+/// 全角文字 (East Asian Width が F, W) は 2 として数える
+pub fn text_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// タブを `tab_width` として数えた文字列の幅
+pub fn line_width(text: &str, tab_width: usize) -> usize {
+    text.split('\t').map(text_width).sum::<usize>() + text.matches('\t').count() * tab_width
+}
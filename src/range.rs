@@ -0,0 +1,195 @@
+use crate::formatter::{next_depth, Formatter};
+use crate::{normalize_newlines, parse, FormatError, OptionData};
+use satysfi_parser::{Cst, Rule, Span};
+use std::ops::{Range, RangeInclusive};
+
+/// 入力の一部を置き換える編集
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    /// 置き換える範囲 (byte 位置)
+    pub range: Range<usize>,
+    pub new_text: String,
+}
+
+/// 指定した範囲だけを format する
+/// 範囲を覆う最小の要素を探し、それらを format した結果を編集として返す
+/// 範囲を覆う単独で format できる要素が無い場合は編集を返さない
+/// * `input` - satysfi のコード
+/// * `range` - format する範囲 (byte 位置)
+pub fn format_range(
+    input: &str,
+    range: Range<usize>,
+    option: OptionData,
+) -> Result<Vec<TextEdit>, FormatError> {
+    // LF に揃えた入力で format し、編集の位置と改行を元の入力に合わせて戻す
+    let original = input;
+    let end_of_line = option.end_of_line.resolve(original);
+    let input = &normalize_newlines(original);
+    let range = normalized_offset(original, range.start)..normalized_offset(original, range.end);
+
+    let csttext = parse(input, option.source_kind)?;
+    let mut selected = vec![];
+    select_nodes(&csttext.cst, &range, 0, &mut selected);
+    let formatter = Formatter::new(&csttext, option);
+
+    // 単独で format できる要素が見つからなければ何も変更しない
+    let edits = selected
+        .into_iter()
+        .map(|(cst, depth)| {
+            let start = cst.span.start;
+            let end = start + input[start..cst.span.end].trim_end().len();
+            let new_text = formatter.format_node(input, cst, depth);
+            // 行頭から要素までが空白だけなら、インデントも揃える
+            let line_start = input[..start].rfind('\n').map_or(0, |index| index + 1);
+            if input[line_start..start].trim().is_empty() {
+                TextEdit {
+                    range: line_start..end,
+                    new_text: formatter.indent(depth) + &new_text,
+                }
+            } else {
+                TextEdit {
+                    range: start..end,
+                    new_text,
+                }
+            }
+        })
+        .map(|edit| TextEdit {
+            range: original_offset(original, edit.range.start)
+                ..original_offset(original, edit.range.end),
+            new_text: end_of_line.apply(&edit.new_text),
+        })
+        .filter(|edit| original[edit.range.clone()] != edit.new_text)
+        .collect();
+    Ok(edits)
+}
+
+/// 元の入力での byte 位置を、改行を LF に揃えた入力での位置に変換する
+fn normalized_offset(original: &str, offset: usize) -> usize {
+    let mut offset = offset.min(original.len());
+    // \r と \n の間の位置は改行の前とみなす
+    if original[..offset].ends_with('\r') && original[offset..].starts_with('\n') {
+        offset -= 1;
+    }
+    offset - original[..offset].matches("\r\n").count()
+}
+
+/// 改行を LF に揃えた入力での byte 位置を、元の入力での位置に変換する
+fn original_offset(original: &str, offset: usize) -> usize {
+    let mut removed = 0;
+    for (index, _) in original.match_indices("\r\n") {
+        if index - removed >= offset {
+            break;
+        }
+        removed += 1;
+    }
+    offset + removed
+}
+
+/// 編集を入力に適用する
+/// 編集の範囲は互いに重ならないものとする
+pub fn apply_edits(input: &str, edits: &[TextEdit]) -> String {
+    let mut edits = edits.iter().collect::<Vec<_>>();
+    edits.sort_by_key(|edit| edit.range.start);
+    let mut output = input.to_string();
+    // 後ろから置き換えることで、前の編集の位置がずれないようにする
+    for edit in edits.into_iter().rev() {
+        output.replace_range(edit.range.clone(), &edit.new_text);
+    }
+    output
+}
+
+/// 1 から数えた行の範囲を byte 位置の範囲に変換する
+/// 終わりの行の改行は範囲に含めない
+pub fn lines_to_range(input: &str, lines: RangeInclusive<usize>) -> Range<usize> {
+    let starts = std::iter::once(0)
+        .chain(input.match_indices('\n').map(|(index, _)| index + 1))
+        .collect::<Vec<_>>();
+    let start = starts
+        .get(lines.start().saturating_sub(1))
+        .copied()
+        .unwrap_or(input.len());
+    let end = starts
+        .get(*lines.end())
+        .map_or(input.len(), |next| next - 1);
+    start..end.max(start)
+}
+
+/// 単独で format して元の位置に置き換えられる規則
+fn is_standalone(rule: Rule) -> bool {
+    matches!(
+        rule,
+        Rule::let_stmt
+            | Rule::let_rec_stmt
+            | Rule::let_inline_stmt_ctx
+            | Rule::let_inline_stmt_noctx
+            | Rule::let_block_stmt_ctx
+            | Rule::let_block_stmt_noctx
+            | Rule::let_math_stmt
+            | Rule::let_mutable_stmt
+            | Rule::type_stmt
+            | Rule::open_stmt
+            | Rule::sig_val_stmt
+            | Rule::sig_type_stmt
+            | Rule::block_cmd
+            | Rule::inline_cmd
+            | Rule::record
+            | Rule::list
+            | Rule::math_text
+    )
+}
+
+fn contains(span: Span, range: &Range<usize>) -> bool {
+    span.start <= range.start && range.end <= span.end
+}
+
+fn overlaps(span: Span, range: &Range<usize>) -> bool {
+    span.start < range.end && range.start < span.end
+}
+
+/// `range` を覆う単独で format できる要素を集める
+/// 範囲を含む子要素があれば、より内側の要素を優先する
+/// * `depth` - `cst` が置かれる位置のインデントの深さ
+fn select_nodes<'a>(
+    cst: &'a Cst,
+    range: &Range<usize>,
+    depth: usize,
+    selected: &mut Vec<(&'a Cst, usize)>,
+) {
+    let inner_depth = next_depth(cst.rule, depth);
+    let child = cst.inner.iter().find(|inner| contains(inner.span, range));
+    if let Some(child) = child {
+        let len = selected.len();
+        select_nodes(child, range, inner_depth, selected);
+        if selected.len() == len && is_standalone(cst.rule) {
+            selected.push((cst, depth));
+        }
+    } else if is_standalone(cst.rule) {
+        selected.push((cst, depth));
+    } else {
+        // 範囲が複数の子要素にまたがる
+        for inner in cst.inner.iter() {
+            if overlaps(inner.span, range) {
+                select_outermost(inner, range, inner_depth, selected);
+            }
+        }
+    }
+}
+
+/// `range` と重なる要素のうち、単独で format できる最も外側の要素を集める
+fn select_outermost<'a>(
+    cst: &'a Cst,
+    range: &Range<usize>,
+    depth: usize,
+    selected: &mut Vec<(&'a Cst, usize)>,
+) {
+    if is_standalone(cst.rule) {
+        selected.push((cst, depth));
+        return;
+    }
+    let inner_depth = next_depth(cst.rule, depth);
+    for inner in cst.inner.iter() {
+        if overlaps(inner.span, range) {
+            select_outermost(inner, range, inner_depth, selected);
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use super::test_tmpl;
+use super::{test_tmpl, test_tmpl_with_option};
+use crate::comment::{align_trailing_comments, find_node_output, wrap_comments};
+use crate::OptionData;
 
 #[test]
 fn test_comment1() {
@@ -178,3 +180,270 @@ document(|title = { hello }|)'<
 "#;
     test_tmpl(text, expect)
 }
+
+#[test]
+fn test_trailing_comment() {
+    let text = r#"let x = 1 % note
+let y = 2
+in
+
+document(||)'<
++p{hello} % note
++p{world}
+>"#;
+
+    let expect = r#"let x = 1 % note
+let y = 2
+in
+
+document(||)'<
+    +p { hello } % note
+    +p { world }
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_trailing_comment_record() {
+    let text = r#"document(|title = {hello}; % title
+author = {author};|)'<>"#;
+
+    let expect = r#"document(|
+    title = { hello }; % title
+    author = { author };
+|)'<>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_trailing_comment_list() {
+    let text = r#"document(||)'<
++align [
+  ${alpha}; % one
+  ${beta};
+];
+>"#;
+
+    let expect = r#"document(||)'<
+    +align [
+        ${alpha}; % one
+        ${beta};
+    ];
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_trailing_comment_short_list() {
+    let text = r#"let x = [1; % one
+2;]
+in
+
+document(||)'<>"#;
+
+    let expect = r#"let x =
+    [
+        1; % one
+        2;
+    ]
+in
+
+document(||)'<>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_dangling_comment() {
+    let text = r#"document(||)'<
++align [
+  ${alpha};
+  % last
+];
++p{hello}
+>"#;
+
+    let expect = r#"document(||)'<
+    +align [
+        ${alpha};
+        % last
+    ];
+    +p { hello }
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_dangling_comment_empty() {
+    let text = r#"document(||)'<
+% nothing yet
+>"#;
+
+    let expect = r#"document(||)'<
+    % nothing yet
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_leading_comment_expr() {
+    let text = r#"let x =
+% note
+  1
+in
+
+document(||)'<>"#;
+
+    let expect = r#"let x =
+    % note
+    1
+in
+
+document(||)'<>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_find_node_output() {
+    let output = "f x-long x (g\n        y)";
+    assert_eq!(find_node_output(output, "x", 1), Some(9..10));
+    // 深いインデントで format し直した要素も最初と最後の行で探す
+    assert_eq!(find_node_output(output, "(g\n    y)", 10), Some(11..24));
+    assert_eq!(find_node_output(output, "", 0), None);
+}
+
+#[test]
+fn test_percent_in_literal() {
+    let text = r#"let s = `100%` % note
+in
+
+document(||)'<
++p{50\% off}
+>"#;
+
+    let expect = r#"let s = `100%` % note
+in
+
+document(||)'<
+    +p { 50\% off }
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_align_trailing_comments() {
+    let text = r#"let x = 1 % x
+let long-name   =   2 % long
+let y = 3 % y
+in
+
+document(||)'<>"#;
+
+    let expect = r#"let x = 1         % x
+let long-name = 2 % long
+let y = 3         % y
+in
+
+document(||)'<>
+"#;
+    let option = OptionData {
+        align_trailing_comments: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
+
+#[test]
+fn test_align_comments() {
+    let output = "let x = 1 % x\nlet long = 2 % long\n% x\n\nlet y = 3 % y\n+p{a}   % y";
+    let comments = ["% x", "% long", "% y", "% y"].map(String::from);
+    // format しなかった範囲の行は揃えない
+    let expect = "let x = 1    % x\nlet long = 2 % long\n% x\n\nlet y = 3 % y\n+p{a}   % y";
+    assert_eq!(
+        align_trailing_comments(output, &comments, 4, &[34..37, 53..64]),
+        expect
+    );
+}
+
+#[test]
+fn test_wrap_comments() {
+    let output = r#"    % aaa bbb ccc ddd eee
+    % fff
+    %
+    % - list item that is long
+    %     code sample that is long
+    % short
+let x = 1"#;
+    let expect = r#"    % aaa bbb ccc
+    % ddd eee fff
+    %
+    % - list item that is long
+    %     code sample that is long
+    % short
+let x = 1"#;
+    assert_eq!(wrap_comments(output, 20, 4, &[]), expect);
+}
+
+#[test]
+fn test_wrap_comments_raw() {
+    let output = "let s = `\n% aaa bbb ccc ddd eee\n`\nlet t = `\n% aaa bbb ccc ddd eee\n`\n% aaa bbb ccc ddd eee";
+    let expect = "let s = `\n% aaa bbb ccc ddd eee\n`\nlet t = `\n% aaa bbb ccc ddd eee\n`\n% aaa bbb ccc\n% ddd eee";
+    assert_eq!(wrap_comments(output, 16, 4, &[8..33, 42..67]), expect);
+}
+
+#[test]
+fn test_wrap_comments_option() {
+    let text = r#"% this comment is too long to fit in the row length so it is wrapped
+let x = 1
+in
+
+document(||)'<>"#;
+
+    let expect = r#"% this comment is too long to fit
+% in the row length so it is
+% wrapped
+let x = 1
+in
+
+document(||)'<>
+"#;
+    let option = OptionData {
+        row_length: 34,
+        wrap_comments: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
+
+#[test]
+fn test_wrap_comments_off() {
+    let text = r#"document(||)'<
+% satysfi-fmt: off
+% this comment is too long to fit in the row length
++p{hello}
+% satysfi-fmt: on
++p{hello}
+>"#;
+
+    let expect = r#"document(||)'<
+    % satysfi-fmt: off
+    % this comment is too long to fit in the row length
+    +p{hello}
+    % satysfi-fmt: on
+    +p { hello }
+>
+"#;
+    let option = OptionData {
+        row_length: 34,
+        wrap_comments: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
@@ -0,0 +1,45 @@
+use super::test_tmpl;
+
+#[test]
+fn test_directive_off_on() {
+    let text = r#"document(||)'<
+% satysfi-fmt: off
++p{hello}
++listing{
+  * item1
+  * item2
+}
+% satysfi-fmt: on
++p{hello}
+>"#;
+
+    let expect = r#"document(||)'<
+    % satysfi-fmt: off
+    +p{hello}
+    +listing{
+  * item1
+  * item2
+}
+    % satysfi-fmt: on
+    +p { hello }
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_directive_skip() {
+    let text = r#"document(||)'<
+%satysfi-fmt: skip
++p{hello}
++p{hello}
+>"#;
+
+    let expect = r#"document(||)'<
+    % satysfi-fmt: skip
+    +p{hello}
+    +p { hello }
+>
+"#;
+    test_tmpl(text, expect)
+}
@@ -0,0 +1,58 @@
+use super::test_tmpl_with_option;
+use crate::{apply_edits, format_range, lines_to_range, EndOfLine, OptionData};
+
+#[test]
+fn test_crlf_preserved() {
+    let text = "@require: stdja\r\n%comment\r\ndocument(||)'<+p{hello}>\r\n";
+    let expect = "@require: stdja\r\n\r\n% comment\r\ndocument(||)'<\r\n    +p { hello }\r\n>\r\n";
+    test_tmpl_with_option(text, expect, OptionData::default())
+}
+
+#[test]
+fn test_crlf_to_lf() {
+    let text = "document(||)'<+p{hello}>\r\n";
+    let expect = "document(||)'<\n    +p { hello }\n>\n";
+    let option = OptionData {
+        end_of_line: EndOfLine::Lf,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
+
+#[test]
+fn test_lf_to_crlf() {
+    let text = "document(||)'<+p{hello}>\n";
+    let expect = "document(||)'<\r\n    +p { hello }\r\n>\r\n";
+    let option = OptionData {
+        end_of_line: EndOfLine::Crlf,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
+
+#[test]
+fn test_resolve() {
+    assert_eq!(EndOfLine::Auto.resolve("a\r\nb\r\nc\n"), EndOfLine::Crlf);
+    assert_eq!(EndOfLine::Auto.resolve("a\nb\r\nc\n"), EndOfLine::Lf);
+    assert_eq!(EndOfLine::Lf.resolve("a\r\nb\r\n"), EndOfLine::Lf);
+}
+
+#[test]
+fn test_format_range_crlf() {
+    let text = "let x  =  1\r\nlet y  =  2\r\nin\r\n\r\ndocument(||)'<>\r\n";
+    let expect = "let x  =  1\r\nlet y = 2\r\nin\r\n\r\ndocument(||)'<>\r\n";
+    let range = lines_to_range(text, 2..=2);
+    let edits = format_range(text, range, OptionData::default()).unwrap();
+    assert_eq!(apply_edits(text, &edits), expect);
+}
+
+#[test]
+fn test_no_final_newline() {
+    let text = "document(||)'<+p{hello}>\n\n";
+    let expect = "document(||)'<\n    +p { hello }\n>";
+    let option = OptionData {
+        insert_final_newline: false,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
@@ -0,0 +1,85 @@
+use crate::{try_format, FormatError, OptionData};
+
+#[test]
+fn test_parse_error() {
+    let text = r#"@require: stdja
+
+document(||)'<
+    +p { hello
+"#;
+    let output = try_format(text, OptionData::default());
+    assert!(matches!(output, Err(FormatError::Parse { .. })));
+}
+
+#[test]
+fn test_render_parse_error() {
+    let source = "document(||)'<\n    +p { hello ]\n>\n";
+    let err = FormatError::Parse {
+        line: 2,
+        column: 16,
+        expected: vec!["\"}\"".to_string(), "\"\\\\\"".to_string()],
+    };
+    let expect = r#"error: failed to parse
+ --> main.saty:2:16
+  |
+1 | document(||)'<
+2 |     +p { hello ]
+  |                ^
+  = expected one of `\`, `}`
+"#;
+    assert_eq!(err.render("main.saty", source), expect);
+}
+
+#[test]
+fn test_render_incomplete() {
+    let source = "document(||)'<\n    +p\n>\n";
+    let err = FormatError::Incomplete {
+        rule: "dummy_block_cmd_incomplete".to_string(),
+        start: 19,
+        end: 21,
+        line: 2,
+        column: 5,
+    };
+    let expect = r#"error: incomplete syntax (dummy_block_cmd_incomplete)
+ --> main.saty:2:5
+  |
+1 | document(||)'<
+2 |     +p
+  |     ^
+"#;
+    assert_eq!(err.render("main.saty", source), expect);
+}
+
+#[test]
+fn test_render_cjk_caret() {
+    let source = "document(||)'<\n    +p { 日本語 ]\n>\n";
+    let err = FormatError::Parse {
+        line: 2,
+        column: 14,
+        expected: vec!["\"}\"".to_string()],
+    };
+    let expect = r#"error: failed to parse
+ --> main.saty:2:14
+  |
+1 | document(||)'<
+2 |     +p { 日本語 ]
+  |                 ^
+  = expected `}`
+"#;
+    assert_eq!(err.render("main.saty", source), expect);
+}
+
+#[test]
+fn test_incomplete_error() {
+    let text = "document(||)'<\n    +p\n>\n";
+    let output = try_format(text, OptionData::default());
+    match output {
+        Err(FormatError::Incomplete {
+            rule, line, column, ..
+        }) => {
+            assert_eq!(rule, "dummy_block_cmd_incomplete");
+            assert_eq!((line, column), (2, 5));
+        }
+        _ => panic!("expected an incomplete syntax error: {output:?}"),
+    }
+}
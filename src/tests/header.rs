@@ -0,0 +1,83 @@
+use super::test_tmpl_with_option;
+use crate::OptionData;
+
+#[test]
+fn test_sort_headers() {
+    let text = r#"@require: stdja
+@import: local
+% for itemize
+@require: itemize
+@require: stdja
+@import: ../lib/a
+
+document(||)'<
++p{hello}
+>"#;
+
+    let expect = r#"% for itemize
+@require: itemize
+@require: stdja
+@import: ../lib/a
+@import: local
+
+document(||)'<
+    +p { hello }
+>
+"#;
+    let option = OptionData {
+        sort_headers: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
+
+#[test]
+fn test_sort_headers_first_comment() {
+    let text = r#"% Copyright (c) 2026
+% Licensed under the MIT License
+
+% for stdja
+@require: stdja
+@require: itemize
+
+document(||)'<>"#;
+
+    let expect = r#"% Copyright (c) 2026
+% Licensed under the MIT License
+
+@require: itemize
+% for stdja
+@require: stdja
+
+document(||)'<>
+"#;
+    let option = OptionData {
+        sort_headers: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
+
+#[test]
+fn test_sort_headers_stage() {
+    let text = r#"@stage: persistent
+% for stdja
+@require: stdja
+@require: itemize
+
+document(||)'<>"#;
+
+    let expect = r#"@stage: persistent
+
+@require: itemize
+% for stdja
+@require: stdja
+
+document(||)'<>
+"#;
+    let option = OptionData {
+        sort_headers: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
@@ -1,4 +1,5 @@
-use super::test_tmpl;
+use super::{test_tmpl, test_tmpl_with_option};
+use crate::OptionData;
 
 #[test]
 fn test_const_string_sequently1() {
@@ -33,3 +34,100 @@ fn test_const_string_sequently2() {
 "#;
     test_tmpl(text, expect)
 }
+
+#[test]
+fn test_cjk_width_short() {
+    // 33 文字 (幅 66) なので 1 行に収まる
+    let text = r#"document(||)'<
+    +p{吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。}
+>
+"#;
+
+    let expect = r#"document(||)'<
+    +p { 吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。 }
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_cjk_width_with_command() {
+    // 幅 66 でもコマンド名の後ろから始めると 1 行に収まらないので改行する
+    let text = r#"document(||)'<
+    +subsection{吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。}
+>
+"#;
+
+    let expect = r#"document(||)'<
+    +subsection {
+        吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。
+    }
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_cjk_width_long() {
+    // 63 文字 (幅 126) なので改行する
+    let text = r#"document(||)'<
+    +p{吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。何でも薄暗いじめじめした所で泣いていた事だけは記憶している。}
+>
+"#;
+
+    let expect = r#"document(||)'<
+    +p {
+        吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。何でも薄暗いじめじめした所で泣いていた事だけは記憶している。
+    }
+>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_fill_text() {
+    let text = r#"document(||)'<
+    +p{Lorem ipsum \bold{dolor sit} amet, consectetur
+    adipiscing elit.}
+>
+"#;
+
+    let expect = r#"document(||)'<
+    +p {
+        Lorem ipsum \bold { dolor sit }
+        amet, consectetur adipiscing
+        elit.
+    }
+>
+"#;
+    let option = OptionData {
+        row_length: 40,
+        fill_text: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
+
+#[test]
+fn test_fill_text_cjk() {
+    // 句読点の前では改行しない
+    let text = r#"document(||)'<
+    +p{吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。}
+>
+"#;
+
+    let expect = r#"document(||)'<
+    +p {
+        吾輩は猫である。名前はまだ無い。
+        どこで生れたかとんと見当がつか
+        ぬ。
+    }
+>
+"#;
+    let option = OptionData {
+        row_length: 40,
+        fill_text: true,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
@@ -0,0 +1,37 @@
+use super::test_tmpl;
+use crate::{check_idempotency, OptionData};
+
+fn test_idempotency(input: &str) {
+    assert_eq!(check_idempotency(input, OptionData::default()), Ok(()));
+}
+
+#[test]
+fn test_examples() {
+    test_idempotency(include_str!("../../examples/ex1.saty"));
+    test_idempotency(include_str!("../../examples/ex2.saty"));
+    test_idempotency(include_str!("../../examples/ex3.saty"));
+}
+
+#[test]
+fn test_doc() {
+    test_idempotency(include_str!("../../doc/doc.saty"));
+    test_idempotency(include_str!("../../doc/draft.saty"));
+}
+
+#[test]
+fn test_blank_line_at_line_start() {
+    // 行頭から始まる要素の前の空行も数える
+    let text = r#"document(||)'<
++p{hello}
+
++p{world}
+>"#;
+
+    let expect = r#"document(||)'<
+    +p { hello }
+
+    +p { world }
+>
+"#;
+    test_tmpl(text, expect)
+}
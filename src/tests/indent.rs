@@ -0,0 +1,31 @@
+use super::test_tmpl_with_option;
+use crate::{IndentStyle, OptionData};
+
+#[test]
+fn test_indent_tab() {
+    let text = r#"document(|title = {hello}|)'<+section{section}<+p{hello world}>>"#;
+
+    let expect = "document(|title = { hello }|)'<\n\t+section { section } <\n\t\t+p { hello world }\n\t>\n>\n";
+    let option = OptionData {
+        indent_style: IndentStyle::Tab,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
+
+#[test]
+fn test_indent_space2() {
+    let text = r#"document(|title = {hello}|)'<+section{section}<+p{hello world}>>"#;
+
+    let expect = r#"document(|title = { hello }|)'<
+  +section { section } <
+    +p { hello world }
+  >
+>
+"#;
+    let option = OptionData {
+        indent_space: 2,
+        ..Default::default()
+    };
+    test_tmpl_with_option(text, expect, option)
+}
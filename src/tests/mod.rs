@@ -1,18 +1,33 @@
-use crate::{format, OptionData};
+use crate::{check_idempotency, format, OptionData};
 
 mod comment;
 mod common;
 mod ctrl_stmt;
+mod directive;
+mod end_of_line;
+mod error;
+mod header;
 mod horizontal_single;
+mod idempotency;
+mod indent;
 mod let_block;
 mod math;
 mod module;
+mod pattern;
+mod pretty;
+mod range;
 mod space;
+mod verify;
 
 fn test_tmpl(input: &str, expect: &str) {
-    let option = OptionData::default();
-    let output = format(input, option);
+    test_tmpl_with_option(input, expect, OptionData::default())
+}
+
+fn test_tmpl_with_option(input: &str, expect: &str, option: OptionData) {
+    let output = format(input, option.clone());
     assert_eq!(output, expect);
+    // もう一度 format しても変わらない
+    assert_eq!(check_idempotency(input, option), Ok(()));
 }
 
 #[test]
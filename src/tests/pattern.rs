@@ -0,0 +1,59 @@
+use super::test_tmpl;
+
+#[test]
+fn test_pattern() {
+    let text = r#"@require: stdja
+let (a,b) = t
+let [ x ;y ] = xs
+let ((c ,d)as e) = t
+let f x =
+  match x with
+  | (a,b)::[] -> a
+  | Some(v)as y -> v
+  | _ -> 0
+in
+
+document(|title = {hello}|)'<
+>"#;
+
+    let expect = r#"@require: stdja
+
+let (a, b) = t
+let [x; y] = xs
+let ((c, d) as e) = t
+let f x =
+    match x with
+        | (a, b) :: [] -> a
+        | Some(v) as y -> v
+        | _ -> 0
+in
+
+document(|title = { hello }|)'<>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_long_pattern() {
+    let text = r#"@require: stdja
+let (first-element, second-element, third-element, fourth-element, fifth-element-of-tuple) = t
+in
+
+document(|title = {hello}|)'<
+>"#;
+
+    let expect = r#"@require: stdja
+
+let (
+    first-element,
+    second-element,
+    third-element,
+    fourth-element,
+    fifth-element-of-tuple
+) = t
+in
+
+document(|title = { hello }|)'<>
+"#;
+    test_tmpl(text, expect)
+}
@@ -0,0 +1,155 @@
+use super::test_tmpl;
+use crate::pretty::{can_break_between, fill, line_width, render, text_width, Doc};
+
+fn application(items: &[&str]) -> Doc {
+    let args = items[1..]
+        .iter()
+        .map(|arg| Doc::concat(vec![Doc::line(), Doc::text(*arg)]))
+        .collect();
+    Doc::group(Doc::concat(vec![
+        Doc::text(items[0]),
+        Doc::nest("  ", Doc::concat(args)),
+    ]))
+}
+
+#[test]
+fn test_render_flat() {
+    let doc = application(&["f", "a", "b"]);
+    assert_eq!(render(&doc, 10, "", 0, 4), "f a b");
+}
+
+#[test]
+fn test_render_break() {
+    let doc = application(&["f", "arg1", "arg2"]);
+    assert_eq!(render(&doc, 10, "", 0, 4), "f\n  arg1\n  arg2");
+    // インデントの分だけ幅が狭くなる
+    let doc = application(&["f", "a", "b"]);
+    assert_eq!(render(&doc, 10, "      ", 6, 4), "f\n        a\n        b");
+}
+
+#[test]
+fn test_render_column() {
+    // 行の途中から置く場合は、その前にある文字列の分だけ幅が狭くなる
+    let doc = application(&["f", "a", "b"]);
+    assert_eq!(render(&doc, 10, "", 4, 4), "f a b");
+    assert_eq!(render(&doc, 10, "", 6, 4), "f\n  a\n  b");
+}
+
+#[test]
+fn test_render_nested_group() {
+    // 外側の group だけが改行され、内側は 1 行に収まる
+    let inner = application(&["g", "x"]);
+    let doc = Doc::group(Doc::concat(vec![
+        Doc::text("f"),
+        Doc::nest(
+            "  ",
+            Doc::concat(vec![Doc::line(), inner, Doc::line(), Doc::text("long-arg")]),
+        ),
+    ]));
+    assert_eq!(render(&doc, 10, "", 0, 4), "f\n  g x\n  long-arg");
+}
+
+#[test]
+fn test_long_application() {
+    let text = r#"let x = some-function argument-number-one argument-number-two argument-number-three argument-number-four
+in
+
+document(||)'<>
+"#;
+    let expect = r#"let x =
+    some-function
+        argument-number-one
+        argument-number-two
+        argument-number-three
+        argument-number-four
+in
+
+document(||)'<>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_let_prefix() {
+    // `let x = ` の後ろに置くと収まらないので、次の行に置く
+    let text = r#"let x = some-function argument-number-one argument-number-two argument-number-three
+in
+
+document(||)'<>
+"#;
+    let expect = r#"let x =
+    some-function argument-number-one argument-number-two argument-number-three
+in
+
+document(||)'<>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_pipeline_prefix() {
+    // `|> ` の後ろに置くと収まらないので、引数ごとに改行する
+    let text = r#"let y = x |> some-function argument-number-one argument-number-two argument-number-three
+in
+
+document(||)'<>
+"#;
+    let expect = r#"let y =
+    x
+        |> some-function
+            argument-number-one
+            argument-number-two
+            argument-number-three
+in
+
+document(||)'<>
+"#;
+    test_tmpl(text, expect)
+}
+
+#[test]
+fn test_text_width() {
+    assert_eq!(text_width("abc"), 3);
+    // 全角文字は 2 として数える
+    assert_eq!(text_width("日本語"), 6);
+    assert_eq!(text_width("ｶﾀｶﾅ"), 4);
+}
+
+#[test]
+fn test_render_cjk() {
+    // 文字数では収まるが、幅では収まらない
+    let doc = application(&["f", "日本語", "テキスト"]);
+    assert_eq!(render(&doc, 12, "", 0, 4), "f\n  日本語\n  テキスト");
+    let doc = application(&["f", "日本", "語"]);
+    assert_eq!(render(&doc, 12, "", 0, 4), "f 日本 語");
+}
+
+#[test]
+fn test_render_tab() {
+    assert_eq!(line_width("\t\tab", 4), 10);
+    // タブのインデントも幅に数える
+    let doc = application(&["f", "a", "b"]);
+    assert_eq!(render(&doc, 10, "\t", 4, 4), "f a b");
+    assert_eq!(render(&doc, 10, "\t\t", 8, 4), "f\n\t\t  a\n\t\t  b");
+}
+
+#[test]
+fn test_fill() {
+    let words = [
+        ("", "ab".to_string()),
+        (" ", "cd".to_string()),
+        ("", "ef".to_string()),
+    ];
+    assert_eq!(fill(&words, 5, "", 4), "ab cd\nef");
+    assert_eq!(fill(&words, 10, "  ", 4), "ab cdef");
+    assert_eq!(fill(&words, 4, "  ", 4), "ab\n  cd\n  ef");
+}
+
+#[test]
+fn test_can_break_between() {
+    assert!(can_break_between('猫', 'で'));
+    // 句読点の前と開き括弧の後では改行しない
+    assert!(!can_break_between('る', '。'));
+    assert!(!can_break_between('「', '猫'));
+    assert!(!can_break_between('a', 'b'));
+}
@@ -0,0 +1,61 @@
+use crate::{apply_edits, format_range, lines_to_range, OptionData, TextEdit};
+
+#[test]
+fn test_lines_to_range() {
+    let text = "abc\ndef\nghi\n";
+    assert_eq!(lines_to_range(text, 1..=1), 0..3);
+    assert_eq!(lines_to_range(text, 2..=3), 4..11);
+    // 範囲外の行は末尾に丸める
+    assert_eq!(lines_to_range(text, 3..=10), 8..12);
+}
+
+#[test]
+fn test_apply_edits() {
+    let text = "abc\ndef\nghi\n";
+    let edits = vec![
+        TextEdit {
+            range: 8..11,
+            new_text: "GHI".to_string(),
+        },
+        TextEdit {
+            range: 0..3,
+            new_text: "A".to_string(),
+        },
+    ];
+    assert_eq!(apply_edits(text, &edits), "A\ndef\nGHI\n");
+}
+
+#[test]
+fn test_format_range() {
+    let text = r#"let x  =  1
+let y  =  2
+in
+
+document(||)'<>
+"#;
+    let expect = r#"let x  =  1
+let y = 2
+in
+
+document(||)'<>
+"#;
+    let range = lines_to_range(text, 2..=2);
+    let edits = format_range(text, range, OptionData::default()).unwrap();
+    assert_eq!(apply_edits(text, &edits), expect);
+}
+
+#[test]
+fn test_format_range_without_statement() {
+    // 範囲を覆う文やコマンドが無い場合は何も変更しない
+    let text = r#"@require:   stdja
+let x  =  1
+in
+
+document(||)'<
+    +p{hello}
+>
+"#;
+    let range = lines_to_range(text, 1..=1);
+    let edits = format_range(text, range, OptionData::default()).unwrap();
+    assert_eq!(edits, vec![]);
+}
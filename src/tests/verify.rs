@@ -0,0 +1,82 @@
+use crate::{check_equivalence, try_format, FormatError, OptionData, SourceKind};
+
+#[test]
+fn test_equivalent() {
+    let text = r#"@require: stdja
+let-block ctx   +newcmd arg = '<+cmd{arg}>
+in
+document(|title = {hello}|)'<+p{hello
+world}>"#;
+    let output = try_format(text, OptionData::default()).unwrap();
+    assert_eq!(check_equivalence(text, &output, SourceKind::Auto), Ok(()));
+}
+
+#[test]
+fn test_not_equivalent() {
+    let text = "document(||)'<\n    +p { hello }\n>\n";
+    let output = "document(||)'<\n    +p { world }\n>\n";
+    let result = check_equivalence(text, output, SourceKind::Auto);
+    assert!(matches!(
+        result,
+        Err(FormatError::NotEquivalent { line: 2, .. })
+    ));
+}
+
+#[test]
+fn test_invalid_output() {
+    let text = "document(||)'<\n    +p { hello }\n>\n";
+    let output = "document(||)'<\n    +p { hello \n>\n";
+    let result = check_equivalence(text, output, SourceKind::Auto);
+    assert!(matches!(result, Err(FormatError::InvalidOutput { .. })));
+}
+
+#[test]
+fn test_render_not_equivalent() {
+    let source = "document(||)'<\n    +p { hello }\n>\n";
+    let err = FormatError::NotEquivalent {
+        rule: "regular_text".to_string(),
+        line: 2,
+        column: 10,
+    };
+    let expect = r#"error: formatting changed the meaning (regular_text)
+ --> main.saty:2:10
+  |
+1 | document(||)'<
+2 |     +p { hello }
+  |          ^
+"#;
+    assert_eq!(err.render("main.saty", source), expect);
+}
+
+#[test]
+fn test_render_not_idempotent() {
+    let err = FormatError::NotIdempotent {
+        line: 3,
+        first: "    +p { hello }".to_string(),
+        second: "".to_string(),
+    };
+    let expect = r#"error: formatting is not idempotent
+ --> main.saty (formatted):3
+  = first:      +p { hello }
+  = second: 
+"#;
+    assert_eq!(err.render("main.saty", ""), expect);
+}
+
+#[test]
+fn test_literal_not_equivalent() {
+    let cases = [
+        ("`a  b`", "`a b`"),
+        ("`x\n  y`", "`x y`"),
+        ("`日本 語`", "`日本語`"),
+    ];
+    for (before, after) in cases {
+        let text = format!("let s = {before}\nin\n\ndocument(||)'<>\n");
+        let output = format!("let s = {after}\nin\n\ndocument(||)'<>\n");
+        let result = check_equivalence(&text, &output, SourceKind::Auto);
+        assert!(matches!(
+            result,
+            Err(FormatError::NotEquivalent { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,135 @@
+use crate::pretty;
+use crate::{line_col, normalize_newlines, parse, try_format, FormatError, OptionData, SourceKind};
+use satysfi_parser::{Cst, Rule};
+
+/// format の前後で意味が変わっていないか確認する
+/// 出力を parse し直し、空白とコメントを除いて元の cst と同じ構造かを比べる
+/// 空白の違いを無視するのは文章 (`regular_text`) の中だけで、文字列リテラルなどは文字通りに比べる
+/// * `input` - format する前の satysfi のコード
+/// * `output` - format した結果
+pub fn check_equivalence(
+    input: &str,
+    output: &str,
+    source_kind: SourceKind,
+) -> Result<(), FormatError> {
+    let input = &normalize_newlines(input);
+    let output = &normalize_newlines(output);
+    let original = parse(input, source_kind)?;
+    let formatted = match parse(output, source_kind) {
+        Ok(formatted) => formatted,
+        Err(FormatError::Parse { line, column, .. })
+        | Err(FormatError::Incomplete { line, column, .. }) => {
+            return Err(FormatError::InvalidOutput { line, column })
+        }
+        Err(err) => return Err(err),
+    };
+    match find_difference(input, &original.cst, output, &formatted.cst) {
+        Some(cst) => {
+            let (line, column) = line_col(&original, cst.span.start);
+            Err(FormatError::NotEquivalent {
+                rule: format!("{:?}", cst.rule),
+                line,
+                column,
+            })
+        }
+        None => Ok(()),
+    }
+}
+
+/// 2 回 format しても結果が変わらないか確認する
+/// 変わる場合は最初に異なる行を返す
+/// * `input` - satysfi のコード
+pub fn check_idempotency(input: &str, option: OptionData) -> Result<(), FormatError> {
+    let first = try_format(input, option.clone())?;
+    let second = try_format(&first, option)?;
+    if first == second {
+        return Ok(());
+    }
+    let mut first_lines = first.split('\n');
+    let mut second_lines = second.split('\n');
+    let mut line = 1;
+    loop {
+        match (first_lines.next(), second_lines.next()) {
+            (Some(a), Some(b)) if a == b => line += 1,
+            (a, b) => {
+                return Err(FormatError::NotIdempotent {
+                    line,
+                    first: a.unwrap_or_default().to_string(),
+                    second: b.unwrap_or_default().to_string(),
+                })
+            }
+        }
+    }
+}
+
+/// 2 つの cst を比べ、最初に異なる元の cst の要素を返す
+fn find_difference<'a>(
+    input: &str,
+    original: &'a Cst,
+    output: &str,
+    formatted: &Cst,
+) -> Option<&'a Cst> {
+    if original.rule != formatted.rule {
+        return Some(original);
+    }
+    let original_text = &input[original.span.start..original.span.end];
+    let formatted_text = &output[formatted.span.start..formatted.span.end];
+    // 文字列リテラルは中の空白にも意味があるので、そのまま比べる
+    if original.rule == Rule::const_string {
+        return (original_text != formatted_text).then_some(original);
+    }
+    let original_inner = significant_inner(input, original);
+    let formatted_inner = significant_inner(output, formatted);
+    if original_inner.is_empty() && formatted_inner.is_empty() {
+        let same = match original.rule {
+            // 文章だけは空白の量や改行の位置が変わってもよい
+            Rule::regular_text => words(original_text) == words(formatted_text),
+            // それ以外は前後の空白を除いてそのまま比べる
+            _ => original_text.trim() == formatted_text.trim(),
+        };
+        return (!same).then_some(original);
+    }
+    for (index, inner) in original_inner.iter().enumerate() {
+        match formatted_inner.get(index) {
+            Some(other) => {
+                if let Some(cst) = find_difference(input, inner, output, other) {
+                    return Some(cst);
+                }
+            }
+            None => return Some(inner),
+        }
+    }
+    if formatted_inner.len() > original_inner.len() {
+        return Some(original);
+    }
+    None
+}
+
+/// 空白やコメントだけの要素を除いた子要素
+fn significant_inner<'a>(text: &str, cst: &'a Cst) -> Vec<&'a Cst> {
+    cst.inner
+        .iter()
+        .filter(|inner| !matches!(inner.rule, Rule::misc | Rule::comments))
+        .filter(|inner| {
+            !inner.inner.is_empty() || !text[inner.span.start..inner.span.end].trim().is_empty()
+        })
+        .collect()
+}
+
+/// 空白の量や改行の位置の違いを無視するため、空白で区切った単語の列にする
+/// 全角文字の間の空白は無視されるので、その前後の単語は繋げる
+fn words(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = vec![];
+    for word in text.split_whitespace() {
+        match words.last_mut() {
+            Some(last)
+                if pretty::is_wide(last.chars().last().unwrap_or(' '))
+                    && pretty::is_wide(word.chars().next().unwrap_or(' ')) =>
+            {
+                *last += word
+            }
+            _ => words.push(word.to_string()),
+        }
+    }
+    words
+}